@@ -1,22 +1,17 @@
-self.onmessage = ({
-    data: { reproMethod, beginningFoodDensity, switchedFoodDensity },
-}) => {
-    // run Rust main
+self.onmessage = ({ data: { reproMethod, beginningFoodDensity, switchedFoodDensity } }) => {
     import("../pkg/index.js").catch(console.error).then(init);
-
     function init(rustModule) {
-        console.log("Getting results...");
-        const results = rustModule.get_results_csv(
-            reproMethod,
-            beginningFoodDensity,
-            switchedFoodDensity
-        );
-        console.log("Got results.");
-        self.postMessage({ type: "finished", results });
+        // the page cancels by terminating this worker, so the token is never cancelled here
+        const cancel = new rustModule.CancellationToken();
+        try {
+            const results = rustModule.get_results_csv(reproMethod, beginningFoodDensity, switchedFoodDensity,
+                (percent) => self.postMessage({ type: "update-percent", percent }),
+                cancel);
+            self.postMessage({ type: "finished", results });
+        } catch (err) {
+            self.postMessage({ type: "error", error: String(err) });
+        } finally {
+            cancel.free();
+        }
     }
-
-    // this is called from rust via eval :^)
-    self.onCompletionPercentChange = (percent) => {
-        self.postMessage({ type: "update-percent", percent });
-    };
 };
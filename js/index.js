@@ -5,7 +5,7 @@ import("../pkg/index.js").catch(console.error).then(init);
 
 let isPlaying = false;
 let steps = 0;
-let rustSimulator = null;
+let simulator = null;
 let context;
 const simWidth = 800;
 let camera = {
@@ -15,30 +15,40 @@ let camera = {
 };
 let stepMultiplier = 1;
 
-function runStep(rustModule) {
+function runStep() {
     updateTime();
-    for (var i = 0; i < stepMultiplier; i++) {
-        rustModule.simulate_step(rustSimulator);
-    }
-    rustModule.render_simulator(context, camera.x, camera.y);
+    simulator.step(stepMultiplier);
+    render();
     if (isPlaying) {
-        requestAnimationFrame(() => runStep(rustModule));
+        requestAnimationFrame(() => runStep());
     }
 }
 
+function render() {
+    simulator.render(context, camera.x, camera.y);
+}
+
 const timer = document.getElementById("time");
 function updateTime() {
     steps += stepMultiplier;
+    showTime();
+}
+function showTime() {
     timer.textContent = `${steps.toLocaleString()} step${steps > 1 ? "s" : ""}`;
+    const extinctionStep = simulator.extinction_step;
+    if (extinctionStep !== undefined) {
+        timer.textContent += ` (extinct at step ${extinctionStep.toLocaleString()})`;
+    }
 }
 
 function init(rustModule) {
+    simulator = new rustModule.Simulator("asexual");
     const canvas = document.getElementById("canvas");
     context = canvas.getContext("2d");
     const updateCanvasDimensions = () => {
         canvas.width = window.innerWidth;
         canvas.height = window.innerHeight;
-        rustModule.render_simulator(context, camera.x, camera.y);
+        render();
     };
     window.onresize = () => {
         updateCanvasDimensions();
@@ -52,8 +62,13 @@ function init(rustModule) {
     const play = document.getElementById("play");
     // actions
     const snapshot = document.getElementById("snapshot");
+    const phylogeny = document.getElementById("phylogeny");
+    const save = document.getElementById("save");
+    const load = document.getElementById("load");
+    const loadFile = document.getElementById("load-file");
     const getResults = document.getElementById("get-results");
     const resultsCover = document.getElementById("results-cover");
+    const cancelResults = document.getElementById("cancel-results");
     const completionPercentages = document.getElementById(
         "completion-percentages"
     );
@@ -74,26 +89,26 @@ function init(rustModule) {
         setTimeout(() => {
             controls.classList.remove("playing");
         }, 50);
-        runStep(rustModule);
+        runStep();
     };
     play.onclick = () => {
         isPlaying = !isPlaying;
         play.textContent = isPlaying ? "Pause" : "Play";
         controls.classList.toggle("playing");
         if (isPlaying) {
-            runStep(rustModule);
+            runStep();
         }
     };
 
     // conf
     foodDensity.onchange = (event) => {
         const newVal = parseInt(event.target.value);
-        rustModule.set_food_density(newVal);
+        simulator.set_food_density(newVal);
     };
     reproRadios.forEach((el) => {
         el.onchange = (event) => {
             const newRepro = event.target.id;
-            rustModule.set_reproductive_method(newRepro);
+            simulator.set_reproductive_method(newRepro);
         };
     });
     stepMultiplierEl.oninput = (_event) => {
@@ -101,10 +116,45 @@ function init(rustModule) {
         stepMultiplierLabelEl.textContent = `Step Multiplier: ${stepMultiplier.toLocaleString()}`;
     };
     snapshot.onclick = () => {
-        download("snapshot.csv", rustModule.get_cells_data_csv());
+        download("snapshot.json", simulator.get_snapshot_json());
+    };
+    phylogeny.onclick = () => {
+        download("phylogeny.nwk", simulator.get_phylogeny_newick());
+    };
+    save.onclick = () => {
+        download("ChoanoSimSave.json", simulator.save());
+    };
+    load.onclick = () => {
+        loadFile.click();
+    };
+    loadFile.onchange = async () => {
+        const file = loadFile.files[0];
+        if (!file) return;
+        try {
+            const loaded = rustModule.Simulator.load(await file.text());
+            simulator.free();
+            simulator = loaded;
+        } catch (err) {
+            alert(`Could not load save: ${err}`);
+            return;
+        } finally {
+            loadFile.value = "";
+        }
+        steps = simulator.steps;
+        showTime();
+        render();
     };
     getResults.onclick = () => {
         resultsCover.style.display = "";
+        // terminating a worker stops its run right away, the next click starts fresh ones
+        const workers = [];
+        cancelResults.onclick = () => {
+            for (const worker of workers) {
+                worker.terminate();
+            }
+            resultsCover.style.display = "none";
+            completionPercentages.textContent = "";
+        };
         setTimeout(async () => {
             const workerConfigurations = [
                 {
@@ -146,10 +196,15 @@ function init(rustModule) {
                 completionPercentages.appendChild(resultsPercentageContainer);
                 const optionsStr = `${config.reproMethod}-${config.beginningFoodDensity}-${config.switchedFoodDensity}`;
                 const worker = new GetResultsWorker();
+                workers.push(worker);
                 worker.onmessage = (msg) => {
                     const { type } = msg.data;
                     let percent;
-                    if (type === "finished") {
+                    if (type === "error") {
+                        resultsPercentage.textContent = `${optionsStr}: failed (${msg.data.error})`;
+                        worker.terminate();
+                        return;
+                    } else if (type === "finished") {
                         const downloadData = () => {
                             download(
                                 `ChoanoSimData-${optionsStr}.csv`,
@@ -172,6 +227,7 @@ function init(rustModule) {
                             );
                         }
                         percent = 100;
+                        worker.terminate();
                     } else if (type === "update-percent") {
                         percent = msg.data.percent;
                     }
@@ -201,19 +257,19 @@ function init(rustModule) {
             case "a":
                 camera.x -= 100;
         }
-        rustModule.render_simulator(context, camera.x, camera.y);
+        render();
     };
     window.onwheel = (event) => {
         camera.x -= event.deltaX;
         camera.y -= event.deltaY;
-        rustModule.render_simulator(context, camera.x, camera.y);
+        render();
     };
     window.onmousemove = (event) => {
         if (event.buttons === 1 && event.target === canvas) {
             event.preventDefault();
             camera.x += event.movementX;
             camera.y += event.movementY;
-            rustModule.render_simulator(context, camera.x, camera.y);
+            render();
         }
     };
 }
@@ -1,20 +1,53 @@
-use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    diploid::Genotype,
     food,
-    genes::Genes,
-    randoms::{random, random_float},
-    simulator::{self, SimulatorConfig},
+    genes::{Gene, Genes},
+    model::ModelParameters,
+    randoms::Random,
+    simulator::SimulatorConfig,
 };
 
+// Unique for the lifetime of a simulator and assigned in increasing order, so a cell can be
+// followed across steps even though its index in the cell list changes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CellId(pub u64);
+
+impl fmt::Display for CellId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// What a father passes on, copied out of the cell so the mother can be changed while it's used
+#[derive(Copy, Clone)]
+pub struct Father {
+    pub id: CellId,
+    pub genes: Genes,
+    pub genotype: Option<Genotype>,
+    pub mating_type: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Cell {
+    id: CellId,
     pub x: u32,
     pub y: u32,
     pub radians: f64,
     pub color: String,
 
     pub genes: Genes,
+    // the alleles the genes come from, only diploid cells have them
+    pub genotype: Option<Genotype>,
     child_genes: Option<Genes>,
+    child_genotype: Option<Genotype>,
+    child_father: Option<CellId>,
+    // see `MatingTypes`, only set when the config has them
+    pub mating_type: Option<u32>,
+    child_mating_type: Option<u32>,
 
     pub alive: bool,
     pub display_seed: f64,
@@ -32,11 +65,14 @@ impl Cell {
         y: u32,
         stomach_amount: f64,
         config: &SimulatorConfig,
+        rng: &mut Random,
     ) -> Self {
         Self {
+            // the simulator gives the cell its real id when it is added
+            id: CellId(0),
             x,
             y,
-            radians: (random(360) as f64).to_radians(),
+            radians: (rng.random(360) as f64).to_radians(),
             stomach_amount,
             rotation_chance: 0.0,
             remaining_steps_until_child_born: 0,
@@ -44,53 +80,82 @@ impl Cell {
             alive: true,
 
             genes,
+            genotype: None,
             child_genes: None,
+            child_genotype: None,
+            child_father: None,
+            mating_type: None,
+            child_mating_type: None,
 
-            display_seed: random_float(),
+            display_seed: rng.random_float(),
             color: format!(
                 "rgb({}, {}, {})",
-                50 + random(100),
-                random(100),
-                100 + random(100)
+                50 + rng.random(100),
+                rng.random(100),
+                100 + rng.random(100)
             ),
         }
     }
 
-    pub fn get_random_pos_in_bounds(config: &SimulatorConfig) -> u32 {
+    pub fn id(&self) -> CellId {
+        self.id
+    }
+
+    pub(crate) fn set_id(&mut self, id: CellId) {
+        self.id = id;
+    }
+
+    // the father of the child this cell is currently carrying, if it was conceived sexually
+    pub fn child_father(&self) -> Option<CellId> {
+        self.child_father
+    }
+
+    pub fn as_father(&self) -> Father {
+        Father {
+            id: self.id,
+            genes: self.genes,
+            genotype: self.genotype,
+            mating_type: self.mating_type,
+        }
+    }
+
+    pub fn get_random_pos_in_bounds(config: &SimulatorConfig, rng: &mut Random) -> u32 {
         let dist_from_wall = config.food_spacing;
-        dist_from_wall / 2 + random(config.width - dist_from_wall)
+        dist_from_wall / 2 + rng.random(config.width - dist_from_wall)
     }
 
-    pub fn simulate_step(&mut self, config: &SimulatorConfig) -> Option<Cell> {
-        self.simulate_movement(config);
+    pub fn simulate_step(&mut self, config: &SimulatorConfig, rng: &mut Random) -> Option<Cell> {
+        self.simulate_movement(config, rng);
 
-        let possibly_new_child = self.simulate_reproduction(config);
+        let possibly_new_child = self.simulate_reproduction(config, rng);
 
-        self.take_food(self.get_energy_usage());
+        self.take_food(self.get_energy_usage(&config.model));
 
-        return possibly_new_child;
+        possibly_new_child
     }
 
-    pub fn simulate_movement(&mut self, config: &SimulatorConfig) {
+    // wall hits turn by `3.14 / 180` per degree, which is part of the model, not a stand-in for PI
+    #[allow(clippy::approx_constant)]
+    pub fn simulate_movement(&mut self, config: &SimulatorConfig, rng: &mut Random) {
         // Explanation:
         // 1. calculate the dx and dy, move if won't go out of bounds
         // 2. slightly increase the chance it will turn
         // 3. if the move would have made the cell go OOB, greatly increase the chance it will turn
         // 4. if a random number is less than that value, rotate randomly
 
-        let speed = self.get_speed();
+        let speed = self.get_speed(&config.model);
         let dx = self.radians.cos() * speed;
         let dy = self.radians.sin() * speed;
         let dist_from_wall = config.food_spacing as i32 / 2i32;
 
-        self.rotation_chance += DEFAULT_ROTATION_CHANCE_CHANGE;
+        self.rotation_chance += config.model.rotation_chance_change;
 
         let new_x = self.x as i32 + dx as i32;
         if new_x >= dist_from_wall && new_x < config.width as i32 - dist_from_wall {
             self.x = new_x as u32;
         } else {
             // self.rotation_chance += ROTATION_CHANCE_CHANGE_ON_WALL_HIT;
-            self.radians += (60.0 + random(70) as f64) * 3.14 / 180.0;
+            self.radians += (60.0 + rng.random(70) as f64) * 3.14 / 180.0;
         }
 
         let new_y = self.y as i32 + dy as i32;
@@ -98,25 +163,27 @@ impl Cell {
             self.y = new_y as u32;
         } else {
             // self.rotation_chance += ROTATION_CHANCE_CHANGE_ON_WALL_HIT;
-            self.radians += (60.0 + random(70) as f64) * 3.14 / 180.0;
+            self.radians += (60.0 + rng.random(70) as f64) * 3.14 / 180.0;
         }
 
-        if random_float() < self.rotation_chance {
-            self.radians = (random(360) as f64).to_radians();
+        if rng.random_float() < self.rotation_chance {
+            self.radians = (rng.random(360) as f64).to_radians();
             self.rotation_chance = 0.0;
         }
     }
 
-    pub fn simulate_reproduction(&mut self, config: &SimulatorConfig) -> Option<Cell> {
-        if self.reproduction_cooldown > 0 {
-            self.reproduction_cooldown -= 1;
-        }
+    pub fn simulate_reproduction(
+        &mut self,
+        config: &SimulatorConfig,
+        rng: &mut Random,
+    ) -> Option<Cell> {
+        self.reproduction_cooldown = self.reproduction_cooldown.saturating_sub(1);
 
         if self.remaining_steps_until_child_born > 0 {
             self.remaining_steps_until_child_born -= 1;
-            self.take_food(CHILD_DEVELOPMENT_FOOD_DECREASE);
+            self.take_food(config.model.child_development_food_decrease);
             if self.remaining_steps_until_child_born == 0 {
-                return self.reproduce(config);
+                return self.reproduce(config, rng);
             }
         }
 
@@ -125,8 +192,9 @@ impl Cell {
 
     pub fn find_food_and_eat(
         &mut self,
-        all_food: &mut Vec<Vec<Option<food::Food>>>,
+        all_food: &mut [Vec<Option<food::Food>>],
         food_spacing: f64,
+        model: &ModelParameters,
     ) {
         let x = self.x as f64;
         let y = self.y as f64;
@@ -137,91 +205,90 @@ impl Cell {
 
         'eat_food: for x_offset in 0..possible_eating_radius {
             for y_offset in 0..possible_eating_radius {
-                let nearest_food_x_offset = (nearest_food_x + x_offset)
-                    .checked_sub(possible_eating_radius / 2)
-                    .unwrap_or(0);
-                let nearest_food_y_offset = (nearest_food_y + y_offset)
-                    .checked_sub(possible_eating_radius / 2)
-                    .unwrap_or(0);
+                let nearest_food_x_offset =
+                    (nearest_food_x + x_offset).saturating_sub(possible_eating_radius / 2);
+                let nearest_food_y_offset =
+                    (nearest_food_y + y_offset).saturating_sub(possible_eating_radius / 2);
 
                 let nearest_food = all_food
                     .get(nearest_food_x_offset)
                     .and_then(|row| row.get(nearest_food_y_offset));
 
-                if let Some(food) = nearest_food {
-                    if let Some(food) = food {
-                        let dist =
-                            ((self.x.abs_diff(food.x) + self.y.abs_diff(food.y)) as f64).sqrt();
-                        if dist < self.get_eating_distance() {
-                            // cell eats the food
-                            self.eat_food();
-                            // remove food
-                            all_food.get_mut(nearest_food_x_offset).unwrap()
-                                [nearest_food_y_offset] = None;
-                            break 'eat_food;
-                        }
+                if let Some(Some(food)) = nearest_food {
+                    let dist = ((self.x.abs_diff(food.x) + self.y.abs_diff(food.y)) as f64).sqrt();
+                    if dist < self.get_eating_distance(model) {
+                        // cell eats the food
+                        self.eat_food(model);
+                        // remove food
+                        all_food.get_mut(nearest_food_x_offset).unwrap()[nearest_food_y_offset] =
+                            None;
+                        break 'eat_food;
                     }
                 }
             }
         }
     }
 
-    pub fn find_mate_and_reproduce(
-        &mut self,
-        index_to_add: usize,
-        reproduction_locations: &mut HashMap<(u32, u32), usize>,
-        config: &simulator::SimulatorConfig,
-    ) -> Option<usize> {
-        // if recently reproduced, don't try to reproduce again.
-        if self.reproduction_cooldown != 0 {
-            return None;
-        }
-
-        // cell reproduction works by finding all cells within REPRODUCTION_DISTANCE in a hashmap
-        // if a cell tries to insert at an already existing key, it will try to reproduce
-        // with the cell there. After successfully reproducing it will remove the value at that
-        // key so other cells can reproduce in the same area.
-        let box_x = (self.x as i32 - REPRODUCTION_DISTANCE) / REPRODUCTION_DISTANCE;
-        let box_y = (self.y as i32 - REPRODUCTION_DISTANCE) / REPRODUCTION_DISTANCE;
-        let box_loc = (box_x as u32, box_y as u32);
-        return if let Some(other_cell) = reproduction_locations.get(&box_loc) {
-            self.reproduction_cooldown = config.reproduction_cooldown;
-            Some(*other_cell)
-        } else {
-            reproduction_locations.insert(box_loc, index_to_add);
-            None
-        };
-    }
-
-    pub fn eat_food(&mut self) {
-        self.stomach_amount += STOMACH_INCREASE_FROM_FOOD_AMOUNT;
+    pub fn eat_food(&mut self, model: &ModelParameters) {
+        self.stomach_amount += model.stomach_increase_from_food_amount;
 
         // if more than full, set to max
-        if self.stomach_amount > self.genes.stomach_size {
-            self.stomach_amount = self.genes.stomach_size;
+        if self.stomach_amount > self.genes[Gene::STOMACH_SIZE] {
+            self.stomach_amount = self.genes[Gene::STOMACH_SIZE];
         }
     }
 
-    pub fn start_reproduction(&mut self, other: &Genes, config: &SimulatorConfig) {
+    // asexual conceptions have no father
+    pub fn start_reproduction(
+        &mut self,
+        father: Option<&Father>,
+        config: &SimulatorConfig,
+        rng: &mut Random,
+    ) {
         // this runs when `self` is impregnated
 
-        self.child_genes = Some(self.genes.mix(other, config));
-        self.remaining_steps_until_child_born = self.genes.steps_until_child_born as u32;
+        if let Some(genotype) = &self.genotype {
+            let father_genotype = father.and_then(|father| father.genotype.as_ref());
+            let child = genotype.offspring(father_genotype, config, rng);
+            self.child_genes = Some(child.phenotype(config));
+            self.child_genotype = Some(child);
+        } else {
+            let other = father.map_or(&self.genes, |father| &father.genes);
+            self.child_genes = Some(self.genes.mix(other, config, rng));
+        }
+        // the type of a random parent
+        self.child_mating_type = match (self.mating_type, father.and_then(|f| f.mating_type)) {
+            (Some(mother), Some(father)) => Some(if rng.random_float() < 0.5 {
+                mother
+            } else {
+                father
+            }),
+            (mother, _) => mother,
+        };
+        self.child_father = father.map(|father| father.id);
+        self.remaining_steps_until_child_born = self.genes[Gene::GESTATION_STEPS] as u32;
         self.reproduction_cooldown = config.reproduction_cooldown;
     }
 
-    fn reproduce(&mut self, config: &SimulatorConfig) -> Option<Self> {
+    fn reproduce(&mut self, config: &SimulatorConfig, rng: &mut Random) -> Option<Self> {
+        self.child_father = None;
         let half_cur_food = self.stomach_amount / 2.0;
         self.take_food(half_cur_food);
         // prevent fast reproduction having no downside -- no birth if lower production
-        if random_float() < 0.18 * self.genes.steps_until_child_born.cbrt() {
-            Some(Cell::new(
+        if rng.random_float()
+            < config.model.birth_chance_multiplier * self.genes[Gene::GESTATION_STEPS].cbrt()
+        {
+            let mut child = Cell::new(
                 self.child_genes.unwrap(),
                 self.x,
                 self.y,
                 half_cur_food,
                 config,
-            ))
+                rng,
+            );
+            child.genotype = self.child_genotype;
+            child.mating_type = self.child_mating_type;
+            Some(child)
         } else {
             None
         }
@@ -237,46 +304,27 @@ impl Cell {
     }
 
     pub fn get_fullness(&self) -> f64 {
-        return self.stomach_amount / self.genes.stomach_size;
+        self.stomach_amount / self.genes[Gene::STOMACH_SIZE]
     }
 
-    pub fn get_speed(&self) -> f64 {
-        self.genes.flagellum_size * FLAGELLUM_SPEED_MULTIPLIER
-            // + self.genes.stomach_size * STOMACH_SIZE_SPEED_MULTIPLIER
-            + self.genes.size.powi(2) * SIZE_SPEED_MULTIPLIER
-            + 5.0
+    pub fn get_speed(&self, model: &ModelParameters) -> f64 {
+        self.genes[Gene::FLAGELLUM_SIZE] * model.flagellum_speed_multiplier
+            // + self.genes[Gene::STOMACH_SIZE] * STOMACH_SIZE_SPEED_MULTIPLIER
+            + self.genes[Gene::SIZE].powi(2) * model.size_speed_multiplier
+            + model.base_speed
     }
 
-    pub fn get_eating_distance(&self) -> f64 {
-        self.genes.size * SIZE_EATING_DISTANCE_MULTIPLIER
-            + self.genes.flagellum_size * FLAGELLUM_SIZE_EATING_DISTANCE_MULTIPLIER
+    pub fn get_eating_distance(&self, model: &ModelParameters) -> f64 {
+        self.genes[Gene::SIZE] * model.size_eating_distance_multiplier
+            + self.genes[Gene::FLAGELLUM_SIZE] * model.flagellum_size_eating_distance_multiplier
     }
 
-    pub fn get_energy_usage(&self) -> f64 {
-        STOMACH_DECREASE_FROM_SIZE_AMOUNT * self.genes.size as f64
-            + STOMACH_DECREASE_FROM_FLA_SIZE_AMOUNT * self.genes.flagellum_size.powi(2) as f64
+    pub fn get_energy_usage(&self, model: &ModelParameters) -> f64 {
+        model.stomach_decrease_from_size_amount * self.genes[Gene::SIZE]
+            + model.stomach_decrease_from_fla_size_amount * self.genes[Gene::FLAGELLUM_SIZE].powi(2)
     }
 
     pub fn die(&mut self) {
         self.alive = false;
     }
 }
-
-const DEFAULT_ROTATION_CHANCE_CHANGE: f64 = 0.0006;
-// const ROTATION_CHANCE_CHANGE_ON_WALL_HIT: f64 = 0.3;
-
-const STOMACH_INCREASE_FROM_FOOD_AMOUNT: f64 = 0.5;
-const STOMACH_DECREASE_FROM_SIZE_AMOUNT: f64 = 0.0026; // 0.018
-const STOMACH_DECREASE_FROM_FLA_SIZE_AMOUNT: f64 = 0.00048;
-
-const FLAGELLUM_SPEED_MULTIPLIER: f64 = 1.5;
-const SIZE_SPEED_MULTIPLIER: f64 = -0.003;
-
-// 1.5 * 5 + 10 * 0.25
-
-const SIZE_EATING_DISTANCE_MULTIPLIER: f64 = 0.25;
-const FLAGELLUM_SIZE_EATING_DISTANCE_MULTIPLIER: f64 = 0.3;
-
-const REPRODUCTION_DISTANCE: i32 = 50;
-
-const CHILD_DEVELOPMENT_FOOD_DECREASE: f64 = 0.01;
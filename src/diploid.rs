@@ -0,0 +1,171 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    genes::{Gene, Genes, Mutation},
+    randoms::Random,
+    simulator::SimulatorConfig,
+};
+
+// How many copies of every gene a cell carries
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ploidy {
+    // one value per gene, children pick each gene from either parent (see `Genes::mix`)
+    #[default]
+    Haploid,
+    // two alleles per gene, one from each parent (see `Genotype`)
+    Diploid,
+}
+
+impl Ploidy {
+    pub const ALL: [Ploidy; 2] = [Ploidy::Haploid, Ploidy::Diploid];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Ploidy::Haploid => "haploid",
+            Ploidy::Diploid => "diploid",
+        }
+    }
+}
+
+impl FromStr for Ploidy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ploidy::ALL
+            .iter()
+            .copied()
+            .find(|ploidy| ploidy.id() == s)
+            .ok_or_else(|| format!("unknown ploidy `{}` (expected `haploid` or `diploid`)", s))
+    }
+}
+
+// The two sets of alleles of a diploid cell, one from each parent. A parent passes on a gamete
+// that recombines its two sets: it starts on a random one and switches before each gene with the
+// config's crossover chance for that gene, so genes with low chances are usually inherited
+// together with the gene before them in the registry. The cell's genes are its phenotype, worked
+// out from both alleles with the config's dominance.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Genotype {
+    pub maternal: Genes,
+    pub paternal: Genes,
+}
+
+impl Genotype {
+    // a founder's alleles, each set drawn like a haploid founder's genes
+    pub fn new(config: &SimulatorConfig, rng: &mut Random) -> Self {
+        let maternal = Genes::new(config, rng);
+        let paternal = Genes::new(config, rng);
+        Genotype { maternal, paternal }
+    }
+
+    // Sexual children get a gamete from each parent, the mother's first. Asexual children are
+    // clones with every allele mutating on its own.
+    pub fn offspring(
+        &self,
+        father: Option<&Genotype>,
+        config: &SimulatorConfig,
+        rng: &mut Random,
+    ) -> Genotype {
+        if let Some(father) = father {
+            let maternal = self.gamete(config, rng);
+            let paternal = father.gamete(config, rng);
+            return Genotype { maternal, paternal };
+        }
+
+        let mutation_chance = self.mutation_chance(config);
+        let mut child = *self;
+        for gene in Gene::all() {
+            let mean = self.mean(gene);
+            for alleles in [&mut child.maternal, &mut child.paternal] {
+                alleles[gene] = mutate(gene, alleles[gene], mean, mutation_chance, config, rng);
+            }
+        }
+        child
+    }
+
+    // one allele of every gene, recombined from both sets and possibly mutated
+    pub fn gamete(&self, config: &SimulatorConfig, rng: &mut Random) -> Genes {
+        let mutation_chance = self.mutation_chance(config);
+        let mut from_maternal = rng.random_float() < 0.5;
+        let mut gamete = self.maternal;
+        for (index, gene) in Gene::all().enumerate() {
+            // the first gene is already on a random set
+            if index > 0 && rng.random_float() < config.crossover_map[gene] {
+                from_maternal = !from_maternal;
+            }
+            let allele = if from_maternal {
+                self.maternal[gene]
+            } else {
+                self.paternal[gene]
+            };
+            gamete[gene] = mutate(gene, allele, self.mean(gene), mutation_chance, config, rng);
+        }
+        gamete
+    }
+
+    // The genes the cell actually has. Each is between the smaller and the larger allele, as far
+    // towards the larger one as the gene's dominance, and then kept within `at_most` like
+    // haploid genes.
+    pub fn phenotype(&self, config: &SimulatorConfig) -> Genes {
+        let mut genes = self.maternal;
+        for gene in Gene::all() {
+            let mut value = self.express(gene, config);
+            if let Some(limit) = gene.definition().at_most {
+                value = value.min(genes[limit]);
+            }
+            genes[gene] = value;
+        }
+        genes
+    }
+
+    pub fn is_heterozygous(&self, gene: Gene) -> bool {
+        self.maternal[gene] != self.paternal[gene]
+    }
+
+    fn express(&self, gene: Gene, config: &SimulatorConfig) -> f64 {
+        let (a, b) = (self.maternal[gene], self.paternal[gene]);
+        let (low, high) = (a.min(b), a.max(b));
+        low + config.dominance[gene] * (high - low)
+    }
+
+    fn mean(&self, gene: Gene) -> f64 {
+        (self.maternal[gene] + self.paternal[gene]) / 2.0
+    }
+
+    // like `Genes::mix`, the cell's own mutation rate if it can evolve
+    fn mutation_chance(&self, config: &SimulatorConfig) -> f64 {
+        if config.evolvable_mutation_rate {
+            self.express(Gene::MUTATION_RATE, config)
+        } else {
+            config.mutation_chance
+        }
+    }
+}
+
+fn mutate(
+    gene: Gene,
+    allele: f64,
+    locus_mean: f64,
+    mutation_chance: f64,
+    config: &SimulatorConfig,
+    rng: &mut Random,
+) -> f64 {
+    if !gene.is_enabled(config) {
+        return allele;
+    }
+
+    let definition = gene.definition();
+    let mut allele = allele;
+    if definition.mutation == Mutation::Default
+        && rng.random_float() < mutation_chance * config.relative_mutation_rates[gene]
+    {
+        allele =
+            config
+                .mutation_kernel
+                .mutate(allele, locus_mean, config.mutation_percent_change, rng);
+    }
+    allele.clamp(definition.min, definition.max)
+}
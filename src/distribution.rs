@@ -0,0 +1,99 @@
+use serde::Serialize;
+
+use crate::genes::Gene;
+use crate::simulator::Simulator;
+
+// How a gene is spread over the population. Means hide a population splitting in two (say small
+// and large cells after food density changes), the shape of the distribution doesn't.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Histogram {
+    pub gene: Gene,
+    // `counts.len() + 1` edges, bin `i` holds values from `edges[i]` up to but not including
+    // `edges[i + 1]`
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+    // values outside the bins, so none go missing without notice
+    pub below: usize,
+    pub above: usize,
+}
+
+impl Histogram {
+    pub fn of(
+        gene: Gene,
+        values: impl IntoIterator<Item = f64>,
+        min: f64,
+        max: f64,
+        bins: usize,
+    ) -> Self {
+        let edges = bin_edges(min, max, bins);
+        let mut histogram = Self {
+            gene,
+            counts: vec![0; bins],
+            edges,
+            below: 0,
+            above: 0,
+        };
+        for value in values {
+            // comparing against the edges themselves puts values on an edge in the same bin
+            // `Metric::GeneShare` does
+            let bin = histogram.edges.partition_point(|&edge| edge <= value);
+            if bin == 0 {
+                histogram.below += 1;
+            } else if bin > bins {
+                histogram.above += 1;
+            } else {
+                histogram.counts[bin - 1] += 1;
+            }
+        }
+        histogram
+    }
+}
+
+// `bins + 1` equally spaced edges from `min` to `max`
+pub fn bin_edges(min: f64, max: f64, bins: usize) -> Vec<f64> {
+    assert!(bins > 0, "a histogram needs at least one bin");
+    assert!(max > min, "the histogram's max has to be above its min");
+    (0..=bins)
+        .map(|bin| min + (max - min) * bin as f64 / bins as f64)
+        .collect()
+}
+
+// The value `quantile` (0 to 1) of the way through `sorted`, interpolating between neighbours
+// like R's and numpy's default. NaN for no values.
+pub fn quantile(sorted: &[f64], quantile: f64) -> f64 {
+    assert!(
+        (0.0..=1.0).contains(&quantile),
+        "quantiles have to be between 0 and 1"
+    );
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = quantile * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+impl Simulator {
+    // every cell's value of `gene`, in cell order
+    pub fn gene_values(&self, gene: Gene) -> Vec<f64> {
+        self.get_cells()
+            .iter()
+            .map(|cell| gene.value(&cell.genes))
+            .collect()
+    }
+
+    pub fn gene_histogram(&self, gene: Gene, min: f64, max: f64, bins: usize) -> Histogram {
+        Histogram::of(gene, self.gene_values(gene), min, max, bins)
+    }
+
+    // e.g. `[0.25, 0.5, 0.75]` for the quartiles, NaN when there are no cells
+    pub fn gene_quantiles(&self, gene: Gene, quantiles: &[f64]) -> Vec<f64> {
+        let mut values = self.gene_values(gene);
+        values.sort_by(f64::total_cmp);
+        quantiles
+            .iter()
+            .map(|&fraction| quantile(&values, fraction))
+            .collect()
+    }
+}
@@ -0,0 +1,655 @@
+use std::io;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::distribution::{self, Histogram};
+use crate::genes::Gene;
+use crate::mating::MatingTypes;
+use crate::progress::{CancellationToken, Cancelled, ProgressObserver};
+use crate::recorder::{Field, Recorder, RowFormat, Sink};
+use crate::simulator::{Simulator, SimulatorConfig};
+use crate::timeline::Timeline;
+
+// A multi-replicate results run, like the one the browser's "Get Results" button starts.
+// Everything about the study is described here, so different horizons or sampling rates don't
+// need any code changes.
+#[derive(Clone)]
+pub struct Experiment {
+    // every replicate starts from this config, replicate `n` is seeded with `config.seed + n`
+    pub config: SimulatorConfig,
+    // environmental changes every replicate goes through
+    pub timeline: Timeline,
+    pub replicates: usize,
+    pub steps: u32,
+    pub sample_interval: u32,
+    // one column each, in this order
+    pub metrics: Vec<Metric>,
+}
+
+impl Experiment {
+    pub fn new(config: SimulatorConfig) -> Self {
+        Self {
+            config,
+            timeline: Timeline::new(),
+            replicates: 10,
+            steps: 1_000_000,
+            sample_interval: 1_000,
+            metrics: Metric::all(&config),
+        }
+    }
+}
+
+// Something measured on every replicate at each sample. Gene means and food are NaN once a
+// population has died out, so they only summarize the replicates that are still alive.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Metric {
+    PopulationSize,
+    FoodAvailable,
+    // the mean of a gene over all cells
+    GeneMean(Gene),
+    // the step the population died out on, NaN while it's alive, so its mean only covers the
+    // replicates that died out (see `Surviving`)
+    TimeToExtinction,
+    // 100 while the population is alive and 0 once it died out, its mean is the % of replicates
+    // left out of the time to extinction
+    Surviving,
+    // the value of `gene` a `quantile` (0 to 1) of the cells are at or below, e.g. 0.5 for the
+    // median. Several of them show when a population spreads out or splits.
+    GeneQuantile { gene: Gene, quantile: f64 },
+    // the % of cells whose `gene` is at least `low` and below `high`, one bin of a histogram
+    GeneShare { gene: Gene, low: f64, high: f64 },
+    // the % of cells of a mating type, with sexes the % of females (0) or males (1)
+    MatingTypeShare(u32),
+}
+
+impl Metric {
+    // population size, % food available, the mean of every gene `config` has, the time to
+    // extinction and how many replicates survived
+    pub fn all(config: &SimulatorConfig) -> Vec<Metric> {
+        Metric::with_ids()
+            .into_iter()
+            .filter(|metric| match metric {
+                Metric::GeneMean(gene) => gene.is_enabled(config),
+                _ => true,
+            })
+            .collect()
+    }
+
+    // like `all` with every gene, the metrics that can be picked by id
+    fn with_ids() -> Vec<Metric> {
+        let mut metrics = vec![Metric::PopulationSize, Metric::FoodAvailable];
+        metrics.extend(Gene::in_output_order().map(Metric::GeneMean));
+        metrics.push(Metric::TimeToExtinction);
+        metrics.push(Metric::Surviving);
+        metrics
+    }
+
+    // the quantiles of `gene`, one metric each
+    pub fn quantiles(gene: Gene, quantiles: &[f64]) -> Vec<Metric> {
+        quantiles
+            .iter()
+            .map(|&quantile| Metric::GeneQuantile { gene, quantile })
+            .collect()
+    }
+
+    // a histogram of `gene` with `bins` equally wide bins from `min` to `max`, one metric per bin
+    pub fn histogram(gene: Gene, min: f64, max: f64, bins: usize) -> Vec<Metric> {
+        distribution::bin_edges(min, max, bins)
+            .windows(2)
+            .map(|edges| Metric::GeneShare {
+                gene,
+                low: edges[0],
+                high: edges[1],
+            })
+            .collect()
+    }
+
+    // the share of every type, none without mating types
+    pub fn mating_types(mating_types: MatingTypes) -> Vec<Metric> {
+        (0..mating_types.count())
+            .map(Metric::MatingTypeShare)
+            .collect()
+    }
+
+    // Used to pick metrics, e.g. on the command line. Quantiles are `<gene>_p<percentile>`
+    // (`size_p50`), histogram bins `<gene>_bin_<low>_<high>` (`size_bin_20_30`) and mating types
+    // `mating_type_<type>`.
+    pub fn id(self) -> String {
+        let id = match self {
+            Metric::PopulationSize => "population",
+            Metric::FoodAvailable => "food",
+            Metric::GeneMean(gene) => gene.id(),
+            Metric::TimeToExtinction => "extinction_step",
+            Metric::Surviving => "surviving",
+            Metric::GeneQuantile { gene, quantile } => {
+                return format!("{}_p{}", gene.id(), percentile(quantile))
+            }
+            Metric::GeneShare { gene, low, high } => {
+                return format!("{}_bin_{}_{}", gene.id(), low, high)
+            }
+            Metric::MatingTypeShare(mating_type) => return format!("mating_type_{}", mating_type),
+        };
+        id.to_string()
+    }
+
+    // the column header
+    pub fn name(self) -> String {
+        let name = match self {
+            Metric::PopulationSize => "Population Size",
+            Metric::FoodAvailable => "% Food Available",
+            Metric::GeneMean(gene) => return format!("Avg. {}", gene.name()),
+            Metric::TimeToExtinction => "Time to Extinction",
+            Metric::Surviving => "% Replicates Surviving",
+            Metric::GeneQuantile { gene, quantile } => {
+                return format!("{} P{}", gene.name(), percentile(quantile))
+            }
+            Metric::GeneShare { gene, low, high } => {
+                return format!("% {} {} to {}", gene.name(), low, high)
+            }
+            Metric::MatingTypeShare(mating_type) => {
+                return format!("% Mating Type {}", mating_type)
+            }
+        };
+        name.to_string()
+    }
+
+    pub fn measure(self, simulator: &Simulator) -> f64 {
+        let cells = simulator.get_cells();
+        match self {
+            Metric::PopulationSize => cells.len() as f64,
+            // extinct worlds stop growing food, so it isn't measured
+            Metric::FoodAvailable if simulator.is_extinct() => f64::NAN,
+            Metric::FoodAvailable => {
+                let food = simulator.get_food();
+                let total_food_avail: usize = food
+                    .iter()
+                    .map(|food_row| food_row.iter().filter(|food| food.is_some()).count())
+                    .sum();
+                let total_food = food.first().expect("could not get food row 0").len() * food.len();
+                (total_food_avail as f64 / total_food as f64) * 100.0
+            }
+            Metric::GeneMean(gene) => {
+                cells.iter().map(|cell| cell.genes[gene]).sum::<f64>() / cells.len() as f64
+            }
+            Metric::TimeToExtinction => simulator
+                .get_extinction_step()
+                .map_or(f64::NAN, |step| step as f64),
+            Metric::Surviving if simulator.is_extinct() => 0.0,
+            Metric::Surviving => 100.0,
+            Metric::GeneQuantile { gene, quantile } => {
+                simulator.gene_quantiles(gene, &[quantile])[0]
+            }
+            // NaN without cells, like the means
+            Metric::GeneShare { gene, low, high } => {
+                let histogram = Histogram::of(gene, simulator.gene_values(gene), low, high, 1);
+                histogram.counts[0] as f64 / cells.len() as f64 * 100.0
+            }
+            Metric::MatingTypeShare(mating_type) => {
+                let count = cells
+                    .iter()
+                    .filter(|cell| cell.mating_type == Some(mating_type))
+                    .count();
+                count as f64 / cells.len() as f64 * 100.0
+            }
+        }
+    }
+}
+
+// 0.5 -> 50, without the float noise of 0.07 * 100
+fn percentile(quantile: f64) -> f64 {
+    (quantile * 100.0 * 1e9).round() / 1e9
+}
+
+// `size_p50` and `size_bin_20_30`
+fn parse_gene_metric(s: &str) -> Option<Result<Metric, String>> {
+    let invalid = || format!("invalid metric `{}`", s);
+    Gene::all().find_map(|gene| {
+        let rest = s.strip_prefix(gene.id())?;
+        if let Some(percentile) = rest.strip_prefix("_p") {
+            let quantile = percentile
+                .parse::<f64>()
+                .map(|percentile| percentile / 100.0);
+            return Some(match quantile {
+                Ok(quantile) if (0.0..=1.0).contains(&quantile) => {
+                    Ok(Metric::GeneQuantile { gene, quantile })
+                }
+                _ => Err(format!("{}, percentiles go from 0 to 100", invalid())),
+            });
+        }
+        let (low, high) = rest.strip_prefix("_bin_")?.split_once('_')?;
+        Some(match (low.parse::<f64>(), high.parse::<f64>()) {
+            (Ok(low), Ok(high)) if low < high => Ok(Metric::GeneShare { gene, low, high }),
+            _ => Err(format!("{}, bins need a low below their high", invalid())),
+        })
+    })
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(metric) = Metric::with_ids()
+            .into_iter()
+            .find(|metric| metric.id() == s)
+        {
+            return Ok(metric);
+        }
+        if let Some(mating_type) = s.strip_prefix("mating_type_") {
+            return mating_type
+                .parse()
+                .map(Metric::MatingTypeShare)
+                .map_err(|_| format!("invalid metric `{}`", s));
+        }
+        parse_gene_metric(s).unwrap_or_else(|| {
+            let ids: Vec<String> = Metric::with_ids()
+                .iter()
+                .map(|metric| metric.id())
+                .collect();
+            Err(format!(
+                "unknown metric `{}` (expected one of {}, <gene>_p<percentile>, \
+                 <gene>_bin_<low>_<high> or mating_type_<type>)",
+                s,
+                ids.join(", ")
+            ))
+        })
+    }
+}
+
+// The CSV columns of `Sample::write`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleColumns {
+    // the mean across replicates of every metric, like the browser's "Get Results" CSV
+    Means,
+    // the mean with the spread across replicates (SD, SE, 95% CI, median and count) next to it
+    Summary,
+    // every replicate's own values, one row per replicate
+    Replicates,
+}
+
+// Every replicate's measurements at one sample step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub step: u32,
+    // one value per metric for every replicate, in replicate order
+    pub replicates: Vec<Vec<f64>>,
+}
+
+impl Sample {
+    pub fn summary(&self, metric: usize) -> Summary {
+        Summary::of(self.replicates.iter().map(|values| values[metric]))
+    }
+
+    // the mean of the `metric`th metric across replicates
+    pub fn mean(&self, metric: usize) -> f64 {
+        self.summary(metric).mean
+    }
+
+    // Writes the sample of an experiment measuring `metrics` through `recorder`. CSV rows have
+    // `columns`, NDJSON gets one line with the spread and every replicate's value of each metric:
+    // `{"step": 1000, "metrics": {"population": {"mean": ..., "replicates": [...]}}}`
+    pub fn write<S: Sink>(
+        &self,
+        metrics: &[Metric],
+        columns: SampleColumns,
+        recorder: &mut Recorder<S>,
+    ) -> io::Result<()> {
+        let step = || ("Step #".to_string(), Field::Integer(self.step as u64));
+        if recorder.format() == RowFormat::Ndjson {
+            return recorder.write_row(&[
+                ("step".to_string(), Field::Integer(self.step as u64)),
+                (
+                    "metrics".to_string(),
+                    Field::Json(self.metrics_json(metrics)),
+                ),
+            ]);
+        }
+
+        match columns {
+            SampleColumns::Means => {
+                let mut row = vec![step()];
+                for (index, metric) in metrics.iter().enumerate() {
+                    row.push((metric.name(), Field::Number(self.mean(index))));
+                }
+                recorder.write_row(&row)
+            }
+            SampleColumns::Summary => {
+                let mut row = vec![step()];
+                for (index, metric) in metrics.iter().enumerate() {
+                    let name = metric.name();
+                    let summary = self.summary(index);
+                    row.extend([
+                        (name.clone(), Field::Number(summary.mean)),
+                        (format!("{} SD", name), Field::Number(summary.sd)),
+                        (format!("{} SE", name), Field::Number(summary.se)),
+                        (
+                            format!("{} 95% CI Low", name),
+                            Field::Number(summary.ci95_low),
+                        ),
+                        (
+                            format!("{} 95% CI High", name),
+                            Field::Number(summary.ci95_high),
+                        ),
+                        (format!("{} Median", name), Field::Number(summary.median)),
+                        (format!("{} N", name), Field::Integer(summary.count as u64)),
+                    ]);
+                }
+                recorder.write_row(&row)
+            }
+            SampleColumns::Replicates => {
+                for (replicate, values) in self.replicates.iter().enumerate() {
+                    let mut row = vec![
+                        step(),
+                        ("Replicate".to_string(), Field::Integer(replicate as u64)),
+                    ];
+                    for (metric, &value) in metrics.iter().zip(values) {
+                        row.push((metric.name(), Field::Number(value)));
+                    }
+                    recorder.write_row(&row)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // `{"population": {"mean": ..., "sd": ..., "replicates": [...]}, ...}`, NaN becomes `null`
+    pub(crate) fn metrics_json(&self, metrics: &[Metric]) -> serde_json::Value {
+        let mut json = serde_json::Map::new();
+        for (index, metric) in metrics.iter().enumerate() {
+            let mut summary = serde_json::to_value(self.summary(index))
+                .expect("summaries should always be serializable");
+            let replicates: Vec<f64> = self.replicates.iter().map(|values| values[index]).collect();
+            summary["replicates"] = json!(replicates);
+            json.insert(metric.id(), summary);
+        }
+        serde_json::Value::Object(json)
+    }
+}
+
+// How one metric is spread across replicates. Undefined values (like the gene means of a
+// population that went extinct) are left out instead of making everything NaN, `count` is the
+// number of replicates that were used. Anything that needs more replicates than that is NaN.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    // sample standard deviation
+    pub sd: f64,
+    // standard error of the mean
+    pub se: f64,
+    // 95% confidence interval of the mean, using Student's t distribution
+    pub ci95_low: f64,
+    pub ci95_high: f64,
+    pub median: f64,
+}
+
+impl Summary {
+    pub fn of(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut values: Vec<f64> = values.into_iter().filter(|value| !value.is_nan()).collect();
+        let count = values.len();
+        let n = count as f64;
+
+        let mean = values.iter().sum::<f64>() / n;
+        let sd = if count > 1 {
+            let squares: f64 = values.iter().map(|value| (value - mean).powi(2)).sum();
+            (squares / (n - 1.0)).sqrt()
+        } else {
+            f64::NAN
+        };
+        let se = sd / n.sqrt();
+        let margin = t_975(count.saturating_sub(1)) * se;
+
+        values.sort_by(f64::total_cmp);
+        let median = match count {
+            0 => f64::NAN,
+            _ if count % 2 == 1 => values[count / 2],
+            _ => (values[count / 2 - 1] + values[count / 2]) / 2.0,
+        };
+
+        Self {
+            count,
+            mean,
+            sd,
+            se,
+            ci95_low: mean - margin,
+            ci95_high: mean + margin,
+            median,
+        }
+    }
+}
+
+// the 97.5th percentile of Student's t distribution with `df` degrees of freedom
+fn t_975(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match df {
+        0 => f64::NAN,
+        1..=30 => TABLE[df - 1],
+        // within 0.005 of the real value from here on
+        _ => 1.96 + 2.4 / df as f64,
+    }
+}
+
+// What an experiment measured, one sample every `sample_interval` steps
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExperimentResults {
+    pub metrics: Vec<Metric>,
+    pub samples: Vec<Sample>,
+}
+
+// Runs every replicate for `experiment.steps` steps and returns the means across replicates
+// every `experiment.sample_interval` steps as CSV. Returns early with `Err(Cancelled)` once
+// `cancel` is cancelled.
+pub fn get_results_csv(
+    experiment: &Experiment,
+    progress: impl ProgressObserver,
+    cancel: &CancellationToken,
+) -> Result<String, Cancelled> {
+    let mut csvs = get_results_csvs(std::slice::from_ref(experiment), progress, cancel)?;
+    Ok(csvs.pop().expect("one CSV per experiment"))
+}
+
+// Like `get_results_csv` for several experiments at once, see `run_experiments`
+pub fn get_results_csvs(
+    experiments: &[Experiment],
+    progress: impl ProgressObserver,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>, Cancelled> {
+    let mut recorders: Vec<Recorder<String>> = experiments
+        .iter()
+        .map(|experiment| Recorder::new(experiment.sample_interval, String::new()))
+        .collect();
+    stream_experiments(
+        experiments,
+        |index, sample| {
+            sample
+                .write(
+                    &experiments[index].metrics,
+                    SampleColumns::Means,
+                    &mut recorders[index],
+                )
+                .expect("strings can always be written to")
+        },
+        progress,
+        cancel,
+    )?;
+    Ok(recorders.into_iter().map(Recorder::into_sink).collect())
+}
+
+pub fn run_experiment(
+    experiment: &Experiment,
+    progress: impl ProgressObserver,
+    cancel: &CancellationToken,
+) -> Result<ExperimentResults, Cancelled> {
+    let mut results = run_experiments(std::slice::from_ref(experiment), progress, cancel)?;
+    Ok(results.pop().expect("one result per experiment"))
+}
+
+// Runs several experiments at once (e.g. to compare configurations) and returns their results in
+// the same order. Natively the replicates of every experiment are stepped on all cores, but
+// results are always combined in replicate order, so seeded runs don't depend on scheduling.
+// `progress` is told the completion percentage of all of them together.
+pub fn run_experiments(
+    experiments: &[Experiment],
+    progress: impl ProgressObserver,
+    cancel: &CancellationToken,
+) -> Result<Vec<ExperimentResults>, Cancelled> {
+    stream_experiments(experiments, |_, _| {}, progress, cancel)
+}
+
+// Like `run_experiments`, also handing every sample to `on_sample` (with the index of its
+// experiment) as soon as all of the experiment's replicates reached it, e.g. to write it out
+// through a `Recorder` while the rest are still running.
+pub fn stream_experiments(
+    experiments: &[Experiment],
+    mut on_sample: impl FnMut(usize, &Sample),
+    mut progress: impl ProgressObserver,
+    cancel: &CancellationToken,
+) -> Result<Vec<ExperimentResults>, Cancelled> {
+    for experiment in experiments {
+        assert!(
+            experiment.sample_interval > 0,
+            "sample interval must be positive"
+        );
+    }
+
+    let mut runs: Vec<Run> = experiments.iter().map(Run::new).collect();
+    let total_steps: u64 = experiments
+        .iter()
+        .map(|experiment| experiment.steps as u64 * experiment.replicates as u64)
+        .sum();
+
+    let mut last_percentage = None;
+    while runs.iter().any(|run| run.step < run.experiment.steps) {
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        // every unfinished experiment advances to its next sample
+        let mut chunks = Vec::new();
+        for run in runs.iter_mut() {
+            let experiment = run.experiment;
+            if run.step >= experiment.steps {
+                continue;
+            }
+            let steps_to_run = experiment.sample_interval.min(experiment.steps - run.step);
+            run.step += steps_to_run;
+            for simulator in run.simulators.iter_mut() {
+                chunks.push(Chunk {
+                    simulator,
+                    steps_to_run,
+                    metrics: &experiment.metrics,
+                });
+            }
+        }
+        let mut values = run_chunks(chunks, cancel).into_iter();
+        // a cancelled chunk stops early, so its values are incomplete
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        for (index, run) in runs.iter_mut().enumerate() {
+            if run.results.samples.last().map(|sample| sample.step) == Some(run.step) {
+                continue;
+            }
+            let replicates = values.by_ref().take(run.simulators.len()).collect();
+            let sample = Sample {
+                step: run.step,
+                replicates,
+            };
+            on_sample(index, &sample);
+            run.results.samples.push(sample);
+        }
+
+        // only update on exact percentages
+        let steps_done: u64 = runs
+            .iter()
+            .map(|run| run.step as u64 * run.simulators.len() as u64)
+            .sum();
+        let completion_percentage = (steps_done * 100 / total_steps.max(1)) as u32;
+        if last_percentage != Some(completion_percentage) {
+            last_percentage = Some(completion_percentage);
+            progress.on_progress(completion_percentage);
+        }
+    }
+
+    Ok(runs.into_iter().map(|run| run.results).collect())
+}
+
+// One experiment in progress
+struct Run<'a> {
+    experiment: &'a Experiment,
+    simulators: Vec<Simulator>,
+    step: u32,
+    results: ExperimentResults,
+}
+
+impl<'a> Run<'a> {
+    fn new(experiment: &'a Experiment) -> Self {
+        let simulators = (0..experiment.replicates)
+            .map(|replicate| {
+                let mut config = experiment.config;
+                config.seed = config.seed.wrapping_add(replicate as u64);
+                let mut simulator = Simulator::with_config(config);
+                simulator.set_timeline(experiment.timeline.clone());
+                simulator
+            })
+            .collect();
+
+        Self {
+            experiment,
+            simulators,
+            step: 0,
+            results: ExperimentResults {
+                metrics: experiment.metrics.clone(),
+                samples: Vec::new(),
+            },
+        }
+    }
+}
+
+// Advances one replicate to its next sample
+struct Chunk<'a> {
+    simulator: &'a mut Simulator,
+    steps_to_run: u32,
+    metrics: &'a [Metric],
+}
+
+fn run_chunk(chunk: Chunk, cancel: &CancellationToken) -> Vec<f64> {
+    let simulator = chunk.simulator;
+    for _ in 0..chunk.steps_to_run {
+        // nothing changes in an extinct world, so there is no need to keep stepping it
+        if cancel.is_cancelled() || simulator.is_extinct() {
+            break;
+        }
+        simulator.simulate_step();
+    }
+    chunk
+        .metrics
+        .iter()
+        .map(|metric| metric.measure(simulator))
+        .collect()
+}
+
+// every simulator is independent, so they can be stepped on separate threads
+#[cfg(not(target_arch = "wasm32"))]
+fn run_chunks(chunks: Vec<Chunk>, cancel: &CancellationToken) -> Vec<Vec<f64>> {
+    use rayon::prelude::*;
+    chunks
+        .into_par_iter()
+        .map(|chunk| run_chunk(chunk, cancel))
+        .collect()
+}
+
+// the browser has no thread pool, its UI runs experiments in web workers instead
+#[cfg(target_arch = "wasm32")]
+fn run_chunks(chunks: Vec<Chunk>, cancel: &CancellationToken) -> Vec<Vec<f64>> {
+    chunks
+        .into_iter()
+        .map(|chunk| run_chunk(chunk, cancel))
+        .collect()
+}
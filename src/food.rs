@@ -1,14 +1,13 @@
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Food {
     pub x: u32,
-    pub y: u32
+    pub y: u32,
 }
 
 impl Food {
     pub fn new(x: u32, y: u32) -> Self {
-        Self {
-            x,
-            y
-        }
+        Self { x, y }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::CellId,
+    genes::{Gene, Genes},
+    simulator::SimulatorConfig,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LineageRecord {
+    pub id: CellId,
+    // The closest maternal ancestor that's still kept, see `Genealogy`. Founders (the cells a
+    // simulation starts with) have none, and neither do cells whose founder was collapsed.
+    pub mother: Option<CellId>,
+    // asexual cells have no father, and the father's record might not be kept
+    pub father: Option<CellId>,
+    pub birth_step: u32,
+    // where this cell's line splits off its mother's. That's its birth step, unless dead
+    // ancestors were collapsed into it, then it's the split step of the oldest of them.
+    pub split_step: u32,
+    pub death_step: Option<u32>,
+    pub genes: Genes,
+    // children whose records are still kept
+    children: Vec<CellId>,
+    // children this cell fathered that haven't been born yet
+    unborn_children: u32,
+}
+
+impl LineageRecord {
+    pub fn children(&self) -> &[CellId] {
+        &self.children
+    }
+}
+
+// The family tree of every living cell, following mothers. Dead cells are only kept where the
+// tree branches (two or more kept children through them as mother) or while a child they
+// fathered is on the way. A dead cell with one such child is collapsed into it, and dead cells
+// without any are removed, so there are never more than twice as many records as living cells
+// plus cells carrying a child, however long the simulation runs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Genealogy {
+    records: BTreeMap<CellId, LineageRecord>,
+}
+
+impl Genealogy {
+    pub fn get(&self, id: CellId) -> Option<&LineageRecord> {
+        self.records.get(&id)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &LineageRecord> {
+        self.records.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    // The tree of the surviving population in Newick format, following mothers (sexual children
+    // also note their father). Every birth splits the mother's line at the child's birth step and
+    // living cells are tips at `current_step`, so branch lengths are in steps. Lines without
+    // living cells are dropped. The genes `config` has are added to living cells as NHX
+    // annotations.
+    pub fn to_newick(&self, current_step: u32, config: &SimulatorConfig) -> String {
+        // (newick, NHX annotation of its top node, step the node is at) for every subtree
+        // containing a living cell, the annotation goes after the branch length leading to it
+        let mut subtrees: HashMap<CellId, (String, String, u32)> = HashMap::new();
+
+        let roots: Vec<CellId> = self
+            .records
+            .values()
+            .filter(|record| record.mother.is_none())
+            .map(|record| record.id)
+            .collect();
+
+        // iterative post-order traversal, lineages can be thousands of generations deep
+        let mut stack: Vec<(CellId, bool)> = roots.iter().rev().map(|&id| (id, false)).collect();
+        while let Some((id, children_done)) = stack.pop() {
+            let record = &self.records[&id];
+            let maternal_children = record
+                .children
+                .iter()
+                .filter(|child| self.records[child].mother == Some(id));
+
+            if !children_done {
+                stack.push((id, true));
+                stack.extend(maternal_children.rev().map(|&child| (child, false)));
+                continue;
+            }
+
+            // the mother's line continues past each birth, so every birth with surviving
+            // descendants splits her line at the child's birth step
+            let mut subtree = if record.death_step.is_none() {
+                Some((
+                    format!("cell{}", record.id),
+                    newick_annotation(record, config),
+                    current_step,
+                ))
+            } else {
+                None
+            };
+            for child in maternal_children.rev() {
+                let child_subtree = match subtrees.remove(child) {
+                    Some(child_subtree) => child_subtree,
+                    None => continue,
+                };
+                subtree = Some(match subtree {
+                    Some(continuation) => {
+                        let split_step = self.records[child].split_step;
+                        (
+                            format!(
+                                "({})",
+                                newick_branches(&[continuation, child_subtree], split_step)
+                            ),
+                            String::new(),
+                            split_step,
+                        )
+                    }
+                    // a dead mother with only one surviving line is collapsed into it
+                    None => child_subtree,
+                });
+            }
+            let subtree = match subtree {
+                Some(subtree) => subtree,
+                None => continue,
+            };
+            subtrees.insert(id, subtree);
+        }
+
+        let mut trees: Vec<(String, String, u32)> = roots
+            .iter()
+            .filter_map(|root| subtrees.remove(root))
+            .collect();
+        match trees.len() {
+            0 => ";".to_string(),
+            1 => {
+                let (newick, annotation, _) = trees.pop().unwrap();
+                format!("{}{};", newick, annotation)
+            }
+            // founders are unrelated, so they hang from a root at step 0
+            _ => format!("({});", newick_branches(&trees, 0)),
+        }
+    }
+
+    pub(crate) fn record_birth(
+        &mut self,
+        id: CellId,
+        mother: Option<CellId>,
+        father: Option<CellId>,
+        birth_step: u32,
+        genes: Genes,
+    ) {
+        for parent in mother.iter().chain(father.iter()) {
+            if let Some(record) = self.records.get_mut(parent) {
+                record.children.push(id);
+            }
+        }
+
+        self.records.insert(
+            id,
+            LineageRecord {
+                id,
+                mother,
+                father,
+                birth_step,
+                split_step: birth_step,
+                death_step: None,
+                genes,
+                children: Vec::new(),
+                unborn_children: 0,
+            },
+        );
+    }
+
+    pub(crate) fn record_death(&mut self, id: CellId, death_step: u32) {
+        if let Some(record) = self.records.get_mut(&id) {
+            record.death_step = Some(death_step);
+        }
+        self.prune(id);
+    }
+
+    pub(crate) fn record_conception(&mut self, father: CellId) {
+        if let Some(record) = self.records.get_mut(&father) {
+            record.unborn_children += 1;
+        }
+    }
+
+    // called once a conceived child is either born or will never be born
+    pub(crate) fn record_conception_ended(&mut self, father: CellId) {
+        if let Some(record) = self.records.get_mut(&father) {
+            record.unborn_children = record.unborn_children.saturating_sub(1);
+        }
+        self.prune(father);
+    }
+
+    // Removes the record of a dead cell without a child on the way that has no children through
+    // it as mother, or collapses it into its child if it has one. Then does the same for its
+    // parents, since they lost a child.
+    fn prune(&mut self, id: CellId) {
+        let mut to_check = vec![id];
+        while let Some(id) = to_check.pop() {
+            let record = match self.records.get(&id) {
+                Some(record) if record.death_step.is_some() && record.unborn_children == 0 => {
+                    record
+                }
+                _ => continue,
+            };
+            let maternal_children: Vec<CellId> = record
+                .children
+                .iter()
+                .copied()
+                .filter(|child| self.records[child].mother == Some(id))
+                .collect();
+            if maternal_children.len() > 1 {
+                continue;
+            }
+
+            let record = self.records.remove(&id).unwrap();
+            // its only line takes its place in its mother's children
+            let heir = maternal_children.first().copied();
+            if let Some(heir) = heir {
+                let heir_record = self.records.get_mut(&heir).unwrap();
+                heir_record.mother = record.mother;
+                heir_record.split_step = record.split_step;
+            }
+            for parent in record.mother.iter().chain(record.father.iter()) {
+                if let Some(parent_record) = self.records.get_mut(parent) {
+                    match (heir, record.mother == Some(*parent)) {
+                        (Some(heir), true) => {
+                            for child in parent_record.children.iter_mut() {
+                                if *child == id {
+                                    *child = heir;
+                                }
+                            }
+                        }
+                        _ => parent_record.children.retain(|&child| child != id),
+                    }
+                    to_check.push(*parent);
+                }
+            }
+        }
+    }
+}
+
+fn newick_branches(branches: &[(String, String, u32)], parent_step: u32) -> String {
+    branches
+        .iter()
+        .map(|(subtree, annotation, step)| {
+            format!("{}:{}{}", subtree, step - parent_step, annotation)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn newick_annotation(record: &LineageRecord, config: &SimulatorConfig) -> String {
+    let mut annotation = format!("[&&NHX:birth_step={}", record.birth_step);
+    for gene in Gene::enabled(config) {
+        annotation += &format!(":{}={}", gene.id(), record.genes[gene]);
+    }
+    if let Some(father) = record.father {
+        annotation += &format!(":father=cell{}", father);
+    }
+    annotation + "]"
+}
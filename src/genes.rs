@@ -1,77 +1,538 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
 use crate::{
-    randoms::{random, random_float},
+    randoms::Random,
     simulator::{Reproduction, SimulatorConfig},
 };
 
-#[derive(Copy, Clone)]
+// Everything about a gene. Exporters, metrics and the genealogy go through the registry, so a
+// new gene only needs an entry here (and whatever behavior it should drive in `Cell`).
+pub struct GeneDefinition {
+    // used in ids, JSON and on the command line
+    pub id: &'static str,
+    // used in column headers
+    pub name: &'static str,
+    // the cells CSV (`Simulator::get_cells_data_csv`) has its own, older headers
+    pub cells_column: &'static str,
+    // what founders start with
+    pub asexual: Initial,
+    pub sexual: Initial,
+    // children's values are kept within these
+    pub min: f64,
+    pub max: f64,
+    // and never above the value of this gene, which has to come earlier in the registry
+    pub at_most: Option<Gene>,
+    pub mutation: Mutation,
+    // Whether a config has the gene. Without it the gene isn't exported or recorded, and
+    // children get their mother's value unchanged.
+    pub enabled: fn(&SimulatorConfig) -> bool,
+}
+
+fn always(_: &SimulatorConfig) -> bool {
+    true
+}
+
+fn with_evolvable_mutation_rate(config: &SimulatorConfig) -> bool {
+    config.evolvable_mutation_rate
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Initial {
+    Fixed(f64),
+    // the config's `mutation_chance`
+    MutationChance,
+    // `low` plus a whole number below `count`
+    Integer { low: f64, count: u32 },
+    // anywhere from `low` up to `low + width`
+    Uniform { low: f64, width: f64 },
+}
+
+impl Initial {
+    fn draw(self, config: &SimulatorConfig, rng: &mut Random) -> f64 {
+        match self {
+            Initial::Fixed(value) => value,
+            Initial::MutationChance => config.mutation_chance,
+            Initial::Integer { low, count } => low + rng.random(count) as f64,
+            Initial::Uniform { low, width } => low + rng.random_float() * width,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    // with the config's `mutation_chance` (times the gene's relative rate), changes by the
+    // config's mutation kernel
+    Default,
+    Never,
+}
+
+// How far a mutation moves a gene. Every kernel is scaled by the config's
+// `mutation_percent_change`, so a timeline or sweep over it works the same for all of them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKernel {
+    // up or down by exactly `mutation_percent_change` of the parents' mean
+    #[default]
+    Fixed,
+    // normally distributed, with `mutation_percent_change` of the parents' mean as its
+    // standard deviation
+    Gaussian,
+    // multiplies by `e^x` with `x` normally distributed with `mutation_percent_change` as its
+    // standard deviation, so changes are relative and genes never change sign
+    LogNormal,
+    // anywhere within `mutation_percent_change` of the parents' mean, up or down
+    Uniform,
+}
+
+impl MutationKernel {
+    pub const ALL: [MutationKernel; 4] = [
+        MutationKernel::Fixed,
+        MutationKernel::Gaussian,
+        MutationKernel::LogNormal,
+        MutationKernel::Uniform,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            MutationKernel::Fixed => "fixed",
+            MutationKernel::Gaussian => "gaussian",
+            MutationKernel::LogNormal => "log_normal",
+            MutationKernel::Uniform => "uniform",
+        }
+    }
+
+    pub(crate) fn mutate(
+        self,
+        chosen: f64,
+        parents_mean: f64,
+        percent: f64,
+        rng: &mut Random,
+    ) -> f64 {
+        let scale = parents_mean * percent;
+        match self {
+            MutationKernel::Fixed => chosen + pick(-1.0, 1.0, rng) * scale,
+            MutationKernel::Gaussian => chosen + rng.random_normal() * scale,
+            MutationKernel::LogNormal => chosen * (rng.random_normal() * percent).exp(),
+            MutationKernel::Uniform => chosen + (rng.random_float() * 2.0 - 1.0) * scale,
+        }
+    }
+}
+
+impl FromStr for MutationKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MutationKernel::ALL
+            .iter()
+            .copied()
+            .find(|kernel| kernel.id() == s)
+            .ok_or_else(|| {
+                let ids: Vec<&str> = MutationKernel::ALL
+                    .iter()
+                    .map(|kernel| kernel.id())
+                    .collect();
+                format!(
+                    "unknown mutation kernel `{}` (expected one of {})",
+                    s,
+                    ids.join(", ")
+                )
+            })
+    }
+}
+
+// Random numbers are drawn gene by gene in this order, so reordering it changes seeded runs.
+// Exports list the genes in `OUTPUT_ORDER` instead.
+pub const REGISTRY: &[GeneDefinition] = &[
+    GeneDefinition {
+        id: "size",
+        name: "Size",
+        cells_column: "size",
+        asexual: Initial::Fixed(30.0),
+        sexual: Initial::Integer {
+            low: 27.0,
+            count: 6,
+        },
+        min: 0.0,
+        max: f64::INFINITY,
+        at_most: None,
+        mutation: Mutation::Default,
+        enabled: always,
+    },
+    GeneDefinition {
+        id: "stomach_size",
+        name: "Stomach Size",
+        cells_column: "stomach size",
+        asexual: Initial::Fixed(10.0),
+        sexual: Initial::Integer { low: 9.0, count: 2 },
+        min: 0.0,
+        max: f64::INFINITY,
+        // prevent stomach from being greater than size
+        at_most: Some(Gene::SIZE),
+        mutation: Mutation::Default,
+        enabled: always,
+    },
+    GeneDefinition {
+        id: "flagellum_size",
+        name: "Flagellum Size",
+        cells_column: "flagellum size",
+        asexual: Initial::Fixed(5.0),
+        sexual: Initial::Uniform {
+            low: 4.5,
+            width: 1.0,
+        },
+        min: 0.0,
+        max: f64::INFINITY,
+        at_most: None,
+        mutation: Mutation::Default,
+        enabled: always,
+    },
+    GeneDefinition {
+        id: "gestation_steps",
+        name: "Gestation Steps",
+        cells_column: "steps until child born",
+        asexual: Initial::Fixed(200.0),
+        sexual: Initial::Integer {
+            low: 195.0,
+            count: 10,
+        },
+        min: 0.0,
+        max: f64::INFINITY,
+        at_most: None,
+        mutation: Mutation::Default,
+        enabled: always,
+    },
+    // The chance of each of a child's genes mutating, only a gene with the config's
+    // `evolvable_mutation_rate`. Otherwise the config's `mutation_chance` is used.
+    GeneDefinition {
+        id: "mutation_rate",
+        name: "Mutation Rate",
+        cells_column: "mutation rate",
+        asexual: Initial::MutationChance,
+        sexual: Initial::MutationChance,
+        min: 0.0,
+        max: 1.0,
+        at_most: None,
+        mutation: Mutation::Default,
+        enabled: with_evolvable_mutation_rate,
+    },
+];
+
+pub const GENE_COUNT: usize = REGISTRY.len();
+
+// The order columns, JSON and the genealogy list genes in, which is the order the exporters had
+// before the registry. New genes go at the end, so existing columns keep their place.
+const OUTPUT_ORDER: [Gene; GENE_COUNT] = [
+    Gene::SIZE,
+    Gene::FLAGELLUM_SIZE,
+    Gene::STOMACH_SIZE,
+    Gene::GESTATION_STEPS,
+    Gene::MUTATION_RATE,
+];
+
+// A gene of the registry
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Gene(usize);
+
+impl Gene {
+    pub const SIZE: Gene = Gene(0);
+    pub const STOMACH_SIZE: Gene = Gene(1);
+    pub const FLAGELLUM_SIZE: Gene = Gene(2);
+    pub const GESTATION_STEPS: Gene = Gene(3);
+    pub const MUTATION_RATE: Gene = Gene(4);
+
+    // in registry order
+    pub fn all() -> impl Iterator<Item = Gene> {
+        (0..GENE_COUNT).map(Gene)
+    }
+
+    // in `OUTPUT_ORDER`, for anything that lists genes
+    pub fn in_output_order() -> impl Iterator<Item = Gene> {
+        OUTPUT_ORDER.iter().copied()
+    }
+
+    pub fn definition(self) -> &'static GeneDefinition {
+        &REGISTRY[self.0]
+    }
+
+    pub fn id(self) -> &'static str {
+        self.definition().id
+    }
+
+    pub fn name(self) -> &'static str {
+        self.definition().name
+    }
+
+    pub fn is_enabled(self, config: &SimulatorConfig) -> bool {
+        (self.definition().enabled)(config)
+    }
+
+    // the genes `config` has, in output order
+    pub fn enabled(config: &SimulatorConfig) -> impl Iterator<Item = Gene> + '_ {
+        Gene::in_output_order().filter(move |gene| gene.is_enabled(config))
+    }
+
+    pub fn value(self, genes: &Genes) -> f64 {
+        genes[self]
+    }
+}
+
+impl fmt::Display for Gene {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id())
+    }
+}
+
+impl FromStr for Gene {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Gene::all().find(|gene| gene.id() == s).ok_or_else(|| {
+            let ids: Vec<&str> = Gene::in_output_order().map(Gene::id).collect();
+            format!("unknown gene `{}` (expected one of {})", s, ids.join(", "))
+        })
+    }
+}
+
+impl Serialize for Gene {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Gene {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        id.parse().map_err(de::Error::custom)
+    }
+}
+
+// The value of every gene of the registry, indexed by `Gene`
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Genes {
-    pub size: f64,
-    pub flagellum_size: f64,
-    pub stomach_size: f64,
-    pub steps_until_child_born: f64,
-    // TODO: gender?
+    values: [f64; GENE_COUNT],
 }
 
 impl Genes {
-    pub fn new(repro_method: Reproduction) -> Self {
-        let is_asexual = match repro_method {
-            Reproduction::Asexual => true,
-            _ => false,
+    // a founder's genes
+    pub fn new(config: &SimulatorConfig, rng: &mut Random) -> Self {
+        let mut values = [0.0; GENE_COUNT];
+        for (value, definition) in values.iter_mut().zip(REGISTRY) {
+            *value = match config.reproduction {
+                Reproduction::Asexual => definition.asexual,
+                Reproduction::Sexual => definition.sexual,
+            }
+            .draw(config, rng);
+        }
+        Genes { values }
+    }
+
+    pub fn mix(&self, other: &Self, config: &SimulatorConfig, rng: &mut Random) -> Self {
+        let mutation_chance = if config.evolvable_mutation_rate {
+            (self[Gene::MUTATION_RATE] + other[Gene::MUTATION_RATE]) / 2.0
+        } else {
+            config.mutation_chance
         };
-        Genes {
-            size: if is_asexual {
-                30.0
-            } else {
-                27.0 + random(6) as f64
-            },
-            stomach_size: if is_asexual {
-                10.0
-            } else {
-                9.0 + random(2) as f64
-            },
-            flagellum_size: if is_asexual {
-                5.0
-            } else {
-                4.5 + random_float()
-            },
-            steps_until_child_born: if is_asexual {
-                200.0
-            } else {
-                195.0 + random(10) as f64
-            },
+
+        let mut child = Genes {
+            values: [0.0; GENE_COUNT],
+        };
+        for gene in Gene::all() {
+            if !gene.is_enabled(config) {
+                child[gene] = self[gene];
+                continue;
+            }
+
+            let definition = gene.definition();
+            let mut value = match definition.mutation {
+                Mutation::Default => pick_with_mutation(
+                    self[gene],
+                    other[gene],
+                    mutation_chance * config.relative_mutation_rates[gene],
+                    config,
+                    rng,
+                ),
+                Mutation::Never => pick(self[gene], other[gene], rng),
+            };
+            value = value.clamp(definition.min, definition.max);
+            if let Some(limit) = definition.at_most {
+                value = value.min(child[limit]);
+            }
+            child[gene] = value;
         }
+        child
+    }
+
+    // every gene with its value, in output order
+    pub fn iter(&self) -> impl Iterator<Item = (Gene, f64)> + '_ {
+        Gene::in_output_order().map(move |gene| (gene, self[gene]))
+    }
+}
+
+impl Index<Gene> for Genes {
+    type Output = f64;
+
+    fn index(&self, gene: Gene) -> &f64 {
+        &self.values[gene.0]
+    }
+}
+
+impl IndexMut<Gene> for Genes {
+    fn index_mut(&mut self, gene: Gene) -> &mut f64 {
+        &mut self.values[gene.0]
     }
-    pub fn mix(&self, other: &Self, config: &SimulatorConfig) -> Self {
-        let size = pick_with_mutation(self.size, other.size, config);
-        return Genes {
-            size,
-            // prevent stomach from being greater than size
-            stomach_size: pick_with_mutation(self.stomach_size, other.stomach_size, config)
-                .min(size),
-            flagellum_size: pick_with_mutation(self.flagellum_size, other.flagellum_size, config),
-            steps_until_child_born: pick_with_mutation(
-                self.steps_until_child_born,
-                other.steps_until_child_born,
-                config,
-            ),
+}
+
+// `{"size": 30.0, "stomach_size": 10.0, ...}`, so saves and snapshots stay readable
+impl Serialize for Genes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter().map(|(gene, value)| (gene.id(), value)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Genes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut saved = BTreeMap::<String, f64>::deserialize(deserializer)?;
+        let mut genes = Genes {
+            values: [0.0; GENE_COUNT],
         };
+        for gene in Gene::all() {
+            genes[gene] = saved
+                .remove(gene.id())
+                .ok_or_else(|| de::Error::missing_field(gene.id()))?;
+        }
+        if let Some(id) = saved.keys().next() {
+            return Err(de::Error::custom(format!("unknown gene `{}`", id)));
+        }
+        Ok(genes)
+    }
+}
+
+// How often each gene mutates compared to the others, e.g. `{"size": 2.0}` for size mutating
+// twice as often. Genes that are left out mutate at the normal rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GeneRates {
+    rates: [f64; GENE_COUNT],
+}
+
+impl Default for GeneRates {
+    fn default() -> Self {
+        Self {
+            rates: [1.0; GENE_COUNT],
+        }
+    }
+}
+
+impl Index<Gene> for GeneRates {
+    type Output = f64;
+
+    fn index(&self, gene: Gene) -> &f64 {
+        &self.rates[gene.0]
+    }
+}
+
+impl IndexMut<Gene> for GeneRates {
+    fn index_mut(&mut self, gene: Gene) -> &mut f64 {
+        &mut self.rates[gene.0]
+    }
+}
+
+impl Serialize for GeneRates {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(Gene::in_output_order().map(|gene| (gene.id(), self[gene])))
+    }
+}
+
+impl<'de> Deserialize<'de> for GeneRates {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut rates = GeneRates::default();
+        deserialize_partial_map(deserializer, &mut rates.rates)?;
+        Ok(rates)
+    }
+}
+
+// A fraction between 0 and 1 for each gene, like the dominance of diploid genes. Genes that are
+// left out are at 0.5.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GeneFractions {
+    fractions: [f64; GENE_COUNT],
+}
+
+impl Default for GeneFractions {
+    fn default() -> Self {
+        Self {
+            fractions: [0.5; GENE_COUNT],
+        }
+    }
+}
+
+impl Index<Gene> for GeneFractions {
+    type Output = f64;
+
+    fn index(&self, gene: Gene) -> &f64 {
+        &self.fractions[gene.0]
+    }
+}
+
+impl IndexMut<Gene> for GeneFractions {
+    fn index_mut(&mut self, gene: Gene) -> &mut f64 {
+        &mut self.fractions[gene.0]
+    }
+}
+
+impl Serialize for GeneFractions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(Gene::in_output_order().map(|gene| (gene.id(), self[gene])))
+    }
+}
+
+impl<'de> Deserialize<'de> for GeneFractions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fractions = GeneFractions::default();
+        deserialize_partial_map(deserializer, &mut fractions.fractions)?;
+        Ok(fractions)
+    }
+}
+
+// overwrites the values of the genes in the map, leaving the rest as they are
+fn deserialize_partial_map<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    values: &mut [f64; GENE_COUNT],
+) -> Result<(), D::Error> {
+    for (id, value) in BTreeMap::<String, f64>::deserialize(deserializer)? {
+        let gene: Gene = id.parse().map_err(de::Error::custom)?;
+        values[gene.0] = value;
     }
+    Ok(())
 }
 
-fn pick_with_mutation(a: f64, b: f64, config: &SimulatorConfig) -> f64 {
-    let mut chosen = pick(a, b);
+fn pick_with_mutation(
+    a: f64,
+    b: f64,
+    mutation_chance: f64,
+    config: &SimulatorConfig,
+    rng: &mut Random,
+) -> f64 {
+    let mut chosen = pick(a, b, rng);
 
-    if random_float() < config.mutation_chance {
-        let sign = pick(-1.0, 1.0);
-        let max_mutation_amount = ((a + b) / 2.0) * config.mutation_percent_change;
-        chosen += sign * max_mutation_amount;
+    if rng.random_float() < mutation_chance {
+        chosen = config.mutation_kernel.mutate(
+            chosen,
+            (a + b) / 2.0,
+            config.mutation_percent_change,
+            rng,
+        );
     }
 
     chosen
 }
 
-fn pick(a: f64, b: f64) -> f64 {
-    if random_float() > 0.5 {
+fn pick(a: f64, b: f64, rng: &mut Random) -> f64 {
+    if rng.random_float() > 0.5 {
         a
     } else {
         b
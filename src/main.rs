@@ -0,0 +1,491 @@
+// Headless runner for the same multi-replicate experiment the browser's "Get Results" button
+// starts, so long runs don't need a browser tab to stay open.
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use choanosim::experiment::{Experiment, Metric, SampleColumns};
+use choanosim::genes::Gene;
+use choanosim::model::ModelParameter;
+use choanosim::progress::CancellationToken;
+use choanosim::recorder::{Recorder, RowFormat, WriteSink};
+use choanosim::sensitivity::Morris;
+use choanosim::simulator::{Reproduction, SimulatorConfig};
+use choanosim::sweep::Sweep;
+use choanosim::timeline::{ConfigField, Timeline};
+
+const USAGE: &str = "\
+Usage: choanosim [OPTIONS]
+
+Options:
+  --reproduction <asexual|sexual>  Reproduction method [default: asexual]
+  --food-densities <LIST>          Comma separated food densities, each used for an equal
+                                   share of the run, e.g. `240,600` [default: 240]
+  --mutation-kernel <KERNEL>       How mutations change genes: `fixed` (up or down by the
+                                   mutation percent change), `gaussian`, `log_normal` or
+                                   `uniform` [default: fixed]
+  --mutation-rate <GENE=RELATIVE>  How often a gene mutates compared to the others, e.g.
+                                   `size=2`. Can be given once per gene
+  --evolvable-mutation-rate        Let every cell carry its own mutation chance as a gene
+  --ploidy <haploid|diploid>       Whether cells have one value per gene or two alleles, one
+                                   from each parent [default: haploid]
+  --dominance <GENE=FRACTION>      How far a diploid gene is from its smaller towards its
+                                   larger allele, e.g. `size=1` for the larger one being
+                                   dominant [default: 0.5]. Can be given once per gene
+  --crossover <GENE=CHANCE>        The chance of diploid gametes switching to the other set of
+                                   alleles before a gene, e.g. `stomach_size=0.05` to keep it
+                                   linked to size [default: 0.5]. Can be given once per gene
+  --mating-types <TYPES>           Who sexual cells can mate with: `none` (anyone), `sexes`
+                                   (females with males) or a number of self-incompatible
+                                   types. Adds the % of cells of each type [default: none]
+  --timeline <PATH>                JSON file of scheduled environmental changes (step
+                                   changes, ramps and periodic cycles) to `food_density`,
+                                   `reproduction_cooldown`, `mutation_chance` or
+                                   `mutation_percent_change`, see the README
+  --replicates <N>                 Number of simulations to average over [default: 10]
+  --steps <N>                      Number of steps to simulate [default: 1000000]
+  --sample-interval <N>            Record a row every N steps [default: 1000]
+  --seed <N>                       Seed for replicate 0, replicate n uses seed + n
+                                   [default: random]
+  --metrics <LIST>                 Comma separated columns to record, out of `population`,
+                                   `food`, `size`, `flagellum_size`, `stomach_size`,
+                                   `gestation_steps`, `mutation_rate` (with an evolvable
+                                   mutation rate), `extinction_step` and `surviving` (% of
+                                   replicates still alive),
+                                   gene percentiles like `size_p90`, histogram bins like
+                                   `size_bin_20_30` and mating types like `mating_type_1`
+                                   [default: all of them]
+  --histogram <GENE=MIN:MAX:BINS>  Add the % of cells in each of BINS equally wide bins of a
+                                   gene, e.g. `size=20:40:10`. Can be given once per gene
+  --quantiles <GENE=LIST>          Add quantiles of a gene, e.g. `flagellum_size=0.1,0.5,0.9`
+  --sweep <FIELD=VALUES>           Run every combination of values for the config fields a
+                                   --timeline can change and output one long-format table
+                                   instead, e.g. `mutation_chance=0.01,0.05` or
+                                   `food_density=100:600:100` (start:end:step). Can be given
+                                   once per field
+  --sensitivity <LIST>             Screen model parameters with Morris elementary effects and
+                                   output how much each drives every metric instead. LIST is
+                                   `all` or comma separated parameters, each either varied by
+                                   --spread around its default (`base_speed`) or over a range
+                                   (`reproduction_distance=30:70`). Only --output applies to
+                                   its table, not --format, --stats or --replicates-output
+  --spread <FRACTION>              How far --sensitivity varies parameters either side of their
+                                   default [default: 0.2]
+  --trajectories <N>               Number of Morris trajectories, each runs the experiment once
+                                   per parameter plus one [default: 10]
+  --stats                          Add the standard deviation, standard error, 95% confidence
+                                   interval, median and replicate count of every metric
+                                   (sweeps always include them)
+  --format <csv|ndjson>            Output format. NDJSON has one JSON object per sample with
+                                   the spread and every replicate's value of each metric
+                                   [default: csv]
+  --replicates-output <PATH>       Also write every replicate's own series to PATH
+  --output <PATH>                  Where to write the CSV [default: stdout]
+  -h, --help                       Print this message";
+
+struct Args {
+    sweep: Sweep,
+    sensitivity: Option<Morris>,
+    stats: bool,
+    ndjson: bool,
+    replicates_output: Option<String>,
+    output: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut experiment = Experiment::new(SimulatorConfig::new(Reproduction::Asexual));
+    let mut food_densities: Vec<u32> = Vec::new();
+    let mut timeline_path = None;
+    let mut swept = Vec::new();
+    let mut metrics = None;
+    let mut distributions = Vec::new();
+    let mut sensitivity = None;
+    let mut spread = 0.2;
+    let mut trajectories = 10;
+    let mut stats = false;
+    let mut ndjson = false;
+    let mut format_given = false;
+    let mut replicates_output = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for `{}`", arg))
+        };
+        match arg.as_str() {
+            "--reproduction" => experiment.config.reproduction = value()?.parse()?,
+            "--food-densities" => {
+                food_densities = value()?
+                    .split(',')
+                    .map(|density| parse_number(density.trim(), "--food-densities"))
+                    .collect::<Result<_, _>>()?;
+            }
+            "--mutation-kernel" => experiment.config.mutation_kernel = value()?.parse()?,
+            "--mutation-rate" => {
+                let (gene, relative) = parse_gene_value(&value()?, &arg, "GENE=RELATIVE")?;
+                experiment.config.relative_mutation_rates[gene] = relative;
+            }
+            "--evolvable-mutation-rate" => experiment.config.evolvable_mutation_rate = true,
+            "--ploidy" => experiment.config.ploidy = value()?.parse()?,
+            "--dominance" => {
+                let (gene, dominance) = parse_gene_value(&value()?, &arg, "GENE=FRACTION")?;
+                experiment.config.dominance[gene] = dominance;
+            }
+            "--crossover" => {
+                let (gene, chance) = parse_gene_value(&value()?, &arg, "GENE=CHANCE")?;
+                experiment.config.crossover_map[gene] = chance;
+            }
+            "--mating-types" => experiment.config.mating_types = value()?.parse()?,
+            "--timeline" => timeline_path = Some(value()?),
+            "--replicates" => experiment.replicates = parse_number(&value()?, &arg)?,
+            "--steps" => experiment.steps = parse_number(&value()?, &arg)?,
+            "--sample-interval" => experiment.sample_interval = parse_number(&value()?, &arg)?,
+            "--seed" => experiment.config.seed = parse_number(&value()?, &arg)?,
+            "--metrics" => {
+                metrics = Some(
+                    value()?
+                        .split(',')
+                        .map(|metric| metric.trim().parse())
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            "--histogram" => distributions.push(parse_histogram(&value()?)?),
+            "--quantiles" => distributions.push(parse_quantiles(&value()?)?),
+            "--sweep" => swept.push(value()?),
+            "--sensitivity" => sensitivity = Some(value()?),
+            "--spread" => spread = parse_number(&value()?, &arg)?,
+            "--trajectories" => trajectories = parse_number(&value()?, &arg)?,
+            "--stats" => stats = true,
+            "--format" => {
+                format_given = true;
+                ndjson = match value()?.as_str() {
+                    "csv" => false,
+                    "ndjson" => true,
+                    format => return Err(format!("unknown format `{}`", format)),
+                }
+            }
+            "--replicates-output" => replicates_output = Some(value()?),
+            "--output" => output = Some(value()?),
+            _ => return Err(format!("unknown argument `{}`", arg)),
+        }
+    }
+
+    // the default depends on the genes the config has, e.g. `--evolvable-mutation-rate`
+    experiment.metrics = metrics.unwrap_or_else(|| Metric::all(&experiment.config));
+    // after the other metrics, however the arguments were ordered
+    experiment
+        .metrics
+        .extend(distributions.into_iter().flatten());
+    for metric in Metric::mating_types(experiment.config.mating_types) {
+        if !experiment.metrics.contains(&metric) {
+            experiment.metrics.push(metric);
+        }
+    }
+
+    if food_densities.contains(&0) {
+        return Err("food densities must be positive".to_string());
+    }
+    if experiment.replicates == 0 {
+        return Err("at least one replicate is required".to_string());
+    }
+    if experiment.sample_interval == 0 {
+        return Err("sample interval must be positive".to_string());
+    }
+
+    // the food density phases come first, so the timeline file can override them
+    let densities: Vec<f64> = food_densities
+        .iter()
+        .map(|&density| density as f64)
+        .collect();
+    experiment.timeline = Timeline::phases(ConfigField::FoodDensity, &densities, experiment.steps);
+    if let Some(path) = timeline_path {
+        let timeline = fs::read_to_string(&path)
+            .map_err(|err| format!("could not read `{}`: {}", path, err))?;
+        let timeline: Timeline = serde_json::from_str(&timeline)
+            .map_err(|err| format!("invalid timeline `{}`: {}", path, err))?;
+        for scheduled in timeline.changes() {
+            experiment.timeline.push(scheduled.field, scheduled.change);
+        }
+    }
+
+    if sensitivity.is_some() && !swept.is_empty() {
+        return Err("`--sensitivity` and `--sweep` can't be combined".to_string());
+    }
+    // the analysis always outputs its one table of effects
+    if sensitivity.is_some() && (stats || format_given || replicates_output.is_some()) {
+        return Err(
+            "`--sensitivity` can't be combined with `--format`, `--stats` or \
+             `--replicates-output`"
+                .to_string(),
+        );
+    }
+    let sensitivity = match sensitivity {
+        Some(parameters) => {
+            let mut morris = Morris::new(experiment.clone());
+            morris.trajectories = trajectories;
+            parse_sensitivity_parameters(&mut morris, &parameters, spread)?;
+            Some(morris)
+        }
+        None => None,
+    };
+
+    let mut sweep = Sweep::new(experiment);
+    for parameter in swept {
+        parse_sweep_parameter(&mut sweep, &parameter)?;
+    }
+    // the timeline (with the food density phases) would replace the swept values
+    for (field, _) in sweep.parameters() {
+        let scheduled = sweep.experiment.timeline.changes().iter();
+        if scheduled.clone().any(|scheduled| scheduled.field == *field) {
+            return Err(format!(
+                "`--sweep {}` can't be combined with --food-densities or a --timeline that \
+                 changes it",
+                field.id()
+            ));
+        }
+    }
+
+    Ok(Args {
+        sweep,
+        sensitivity,
+        stats,
+        ndjson,
+        replicates_output,
+        output,
+    })
+}
+
+// `field=a,b,c` or `field=start:end:step`
+fn parse_sweep_parameter(sweep: &mut Sweep, parameter: &str) -> Result<(), String> {
+    let (field, values) = parameter
+        .split_once('=')
+        .ok_or_else(|| format!("invalid sweep `{}`, expected FIELD=VALUES", parameter))?;
+    let field: ConfigField = field.trim().parse()?;
+
+    let range: Vec<&str> = values.split(':').collect();
+    if let [start, end, step] = range[..] {
+        let step: f64 = parse_number(step.trim(), "--sweep")?;
+        if step <= 0.0 {
+            return Err("sweep step must be positive".to_string());
+        }
+        let start = parse_number(start.trim(), "--sweep")?;
+        let end = parse_number(end.trim(), "--sweep")?;
+        if end < start {
+            return Err(format!(
+                "invalid sweep `{}`, the range ends before it starts",
+                parameter
+            ));
+        }
+        field.check(start)?;
+        field.check(end)?;
+        sweep.vary_range(field, start, end, step);
+    } else {
+        let values: Vec<f64> = values
+            .split(',')
+            .map(|value| parse_number(value.trim(), "--sweep"))
+            .collect::<Result<_, _>>()?;
+        for &value in &values {
+            field.check(value)?;
+        }
+        sweep.vary(field, values);
+    }
+    Ok(())
+}
+
+// `all`, or a list of `parameter` and `parameter=low:high`
+fn parse_sensitivity_parameters(
+    morris: &mut Morris,
+    parameters: &str,
+    spread: f64,
+) -> Result<(), String> {
+    if parameters.trim() == "all" {
+        for parameter in ModelParameter::ALL {
+            morris.vary_around(parameter, spread);
+        }
+        return Ok(());
+    }
+
+    for parameter in parameters.split(',') {
+        match parameter.split_once('=') {
+            Some((parameter, range)) => {
+                let parameter: ModelParameter = parameter.trim().parse()?;
+                let (low, high) = range
+                    .split_once(':')
+                    .ok_or_else(|| format!("invalid range `{}`, expected LOW:HIGH", range))?;
+                morris.vary(
+                    parameter,
+                    parse_number(low.trim(), "--sensitivity")?,
+                    parse_number(high.trim(), "--sensitivity")?,
+                );
+            }
+            None => {
+                morris.vary_around(parameter.trim().parse()?, spread);
+            }
+        }
+    }
+    Ok(())
+}
+
+// `gene=value`
+fn parse_gene_value(setting: &str, arg: &str, expected: &str) -> Result<(Gene, f64), String> {
+    let (gene, value) = setting
+        .split_once('=')
+        .ok_or_else(|| format!("invalid `{}` `{}`, expected {}", arg, setting, expected))?;
+    Ok((gene.trim().parse()?, parse_number(value.trim(), arg)?))
+}
+
+// `gene=min:max:bins`
+fn parse_histogram(histogram: &str) -> Result<Vec<Metric>, String> {
+    let invalid = || {
+        format!(
+            "invalid histogram `{}`, expected GENE=MIN:MAX:BINS",
+            histogram
+        )
+    };
+    let (gene, bins) = histogram.split_once('=').ok_or_else(invalid)?;
+    let gene: Gene = gene.trim().parse()?;
+    let [min, max, bins] = bins.split(':').collect::<Vec<_>>()[..] else {
+        return Err(invalid());
+    };
+    let min: f64 = parse_number(min.trim(), "--histogram")?;
+    let max: f64 = parse_number(max.trim(), "--histogram")?;
+    let bins: usize = parse_number(bins.trim(), "--histogram")?;
+    if bins == 0 || max <= min {
+        return Err("histograms need bins and a max above their min".to_string());
+    }
+    Ok(Metric::histogram(gene, min, max, bins))
+}
+
+// `gene=a,b,c`
+fn parse_quantiles(quantiles: &str) -> Result<Vec<Metric>, String> {
+    let (gene, values) = quantiles
+        .split_once('=')
+        .ok_or_else(|| format!("invalid quantiles `{}`, expected GENE=LIST", quantiles))?;
+    let gene: Gene = gene.trim().parse()?;
+    let values: Vec<f64> = values
+        .split(',')
+        .map(|value| parse_number(value.trim(), "--quantiles"))
+        .collect::<Result<_, _>>()?;
+    if values.iter().any(|value| !(0.0..=1.0).contains(value)) {
+        return Err("quantiles have to be between 0 and 1".to_string());
+    }
+    Ok(Metric::quantiles(gene, &values))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, arg: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, arg))
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let progress = |completion_percentage| {
+        eprint!("\r{}% complete", completion_percentage);
+        let _ = io::stderr().flush();
+    };
+    let cancel = CancellationToken::new();
+    if let Some(morris) = &args.sensitivity {
+        let results = morris
+            .run(progress, &cancel)
+            .expect("the analysis is never cancelled");
+        eprintln!();
+        write_results(args.output.as_ref(), results.to_csv());
+        return;
+    }
+
+    // rows are written as soon as every replicate reached their sample
+    let interval = args.sweep.experiment.sample_interval;
+    let mut output = Recorder::new(interval, WriteSink(create_output(args.output.as_ref())));
+    if args.ndjson {
+        output.set_format(RowFormat::Ndjson);
+    }
+    let mut replicates = args
+        .replicates_output
+        .as_ref()
+        .map(|path| Recorder::new(interval, WriteSink(create_output(Some(path)))));
+
+    let sweep = &args.sweep;
+    let metrics = &sweep.experiment.metrics;
+    let columns = if args.stats {
+        SampleColumns::Summary
+    } else {
+        SampleColumns::Means
+    };
+    let mut failed = None;
+    let run = sweep.stream(
+        |combination, sample| {
+            let written = if sweep.parameters().is_empty() {
+                sample.write(metrics, columns, &mut output).and_then(|_| {
+                    replicates.as_mut().map_or(Ok(()), |replicates| {
+                        sample.write(metrics, SampleColumns::Replicates, replicates)
+                    })
+                })
+            } else {
+                sweep
+                    .write_sample(combination, sample, &mut output)
+                    .and_then(|_| {
+                        replicates.as_mut().map_or(Ok(()), |replicates| {
+                            sweep.write_replicates(combination, sample, replicates)
+                        })
+                    })
+            };
+            // there's no point in running on if the results can't be written
+            if let Err(err) = written {
+                failed.get_or_insert(err);
+                cancel.cancel();
+            }
+        },
+        progress,
+        &cancel,
+    );
+    eprintln!();
+
+    let flushed = output
+        .flush()
+        .and_then(|_| replicates.as_mut().map_or(Ok(()), Recorder::flush));
+    if let Some(err) = failed.or(flushed.err()) {
+        eprintln!("error: could not write results: {}", err);
+        process::exit(1);
+    }
+    run.expect("the experiment is only cancelled when writing fails");
+}
+
+// a buffered file, or stdout without a path
+fn create_output(path: Option<&String>) -> Box<dyn Write> {
+    match path {
+        Some(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("error: could not create `{}`: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    }
+}
+
+fn write_results(path: Option<&String>, results: String) {
+    let written = match path {
+        Some(path) => fs::write(path, results.trim_end().to_string() + "\n"),
+        None => writeln!(io::stdout(), "{}", results.trim_end()),
+    };
+    if let Err(err) = written {
+        eprintln!("error: could not write results: {}", err);
+        process::exit(1);
+    }
+}
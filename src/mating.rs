@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::randoms::Random;
+
+// with `MatingTypes::Sexes`
+pub const FEMALE: u32 = 0;
+pub const MALE: u32 = 1;
+
+// Who can mate with whom in sexual populations. Cells get a random type when a simulation
+// starts, and children get the type of a random one of their parents (asexual children their
+// mother's), so rare types are more likely to find a mate and tend to come back.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatingTypes {
+    // any two cells can mate
+    #[default]
+    None,
+    // females (`FEMALE`) and males (`MALE`), only females carry children
+    Sexes,
+    // this many types, cells only mate with another type and either of them can carry the child
+    SelfIncompatible(u32),
+}
+
+impl MatingTypes {
+    // the types are 0 up to this, without mating types there are none
+    pub fn count(self) -> u32 {
+        match self {
+            MatingTypes::None => 0,
+            MatingTypes::Sexes => 2,
+            MatingTypes::SelfIncompatible(count) => count,
+        }
+    }
+
+    // whether cells of these types can mate, cells without a type count as type 0
+    pub fn can_mate(self, a: Option<u32>, b: Option<u32>) -> bool {
+        self == MatingTypes::None || a.unwrap_or(0) != b.unwrap_or(0)
+    }
+
+    // a founder's type
+    pub fn draw(self, rng: &mut Random) -> Option<u32> {
+        match self {
+            MatingTypes::None => None,
+            _ => Some(rng.random(self.count())),
+        }
+    }
+}
+
+impl FromStr for MatingTypes {
+    type Err = String;
+
+    // `none`, `sexes` or a number of self-incompatible types
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(MatingTypes::None),
+            "sexes" => Ok(MatingTypes::Sexes),
+            _ => match s.parse() {
+                Ok(count) if count >= 2 => Ok(MatingTypes::SelfIncompatible(count)),
+                _ => Err(format!(
+                    "unknown mating types `{}` (expected `none`, `sexes` or a number of \
+                     self-incompatible types, at least 2)",
+                    s
+                )),
+            },
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// The constants of how cells move, eat and reproduce. They used to be hard coded; having them
+// in the config lets sensitivity analyses (see `sensitivity`) check how much each one matters.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelParameters {
+    // how much more likely a cell gets to turn every step it doesn't
+    pub rotation_chance_change: f64,
+
+    pub stomach_increase_from_food_amount: f64,
+    // energy used every step per unit of size
+    pub stomach_decrease_from_size_amount: f64,
+    // energy used every step per squared unit of flagellum size
+    pub stomach_decrease_from_fla_size_amount: f64,
+
+    pub base_speed: f64,
+    pub flagellum_speed_multiplier: f64,
+    // per squared unit of size
+    pub size_speed_multiplier: f64,
+
+    pub size_eating_distance_multiplier: f64,
+    pub flagellum_size_eating_distance_multiplier: f64,
+
+    // sexual cells mate with a cell in the same square of this size
+    pub reproduction_distance: u32,
+    // food a pregnant cell uses every step on top of its usual energy
+    pub child_development_food_decrease: f64,
+    // a child is born with `birth_chance_multiplier * cbrt(gestation steps)` probability, so fast
+    // reproduction has a downside
+    pub birth_chance_multiplier: f64,
+}
+
+impl Default for ModelParameters {
+    fn default() -> Self {
+        Self {
+            rotation_chance_change: 0.0006,
+            // rotation chance change on wall hit: 0.3
+            stomach_increase_from_food_amount: 0.5,
+            stomach_decrease_from_size_amount: 0.0026, // 0.018
+            stomach_decrease_from_fla_size_amount: 0.00048,
+            base_speed: 5.0,
+            flagellum_speed_multiplier: 1.5,
+            size_speed_multiplier: -0.003,
+            // 1.5 * 5 + 10 * 0.25
+            size_eating_distance_multiplier: 0.25,
+            flagellum_size_eating_distance_multiplier: 0.3,
+            reproduction_distance: 50,
+            child_development_food_decrease: 0.01,
+            birth_chance_multiplier: 0.18,
+        }
+    }
+}
+
+// Names a model parameter, like `ConfigField` does for the rest of the config
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelParameter {
+    RotationChanceChange,
+    StomachIncreaseFromFoodAmount,
+    StomachDecreaseFromSizeAmount,
+    StomachDecreaseFromFlaSizeAmount,
+    BaseSpeed,
+    FlagellumSpeedMultiplier,
+    SizeSpeedMultiplier,
+    SizeEatingDistanceMultiplier,
+    FlagellumSizeEatingDistanceMultiplier,
+    ReproductionDistance,
+    ChildDevelopmentFoodDecrease,
+    BirthChanceMultiplier,
+}
+
+impl ModelParameter {
+    pub const ALL: [ModelParameter; 12] = [
+        ModelParameter::RotationChanceChange,
+        ModelParameter::StomachIncreaseFromFoodAmount,
+        ModelParameter::StomachDecreaseFromSizeAmount,
+        ModelParameter::StomachDecreaseFromFlaSizeAmount,
+        ModelParameter::BaseSpeed,
+        ModelParameter::FlagellumSpeedMultiplier,
+        ModelParameter::SizeSpeedMultiplier,
+        ModelParameter::SizeEatingDistanceMultiplier,
+        ModelParameter::FlagellumSizeEatingDistanceMultiplier,
+        ModelParameter::ReproductionDistance,
+        ModelParameter::ChildDevelopmentFoodDecrease,
+        ModelParameter::BirthChanceMultiplier,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            ModelParameter::RotationChanceChange => "rotation_chance_change",
+            ModelParameter::StomachIncreaseFromFoodAmount => "stomach_increase_from_food_amount",
+            ModelParameter::StomachDecreaseFromSizeAmount => "stomach_decrease_from_size_amount",
+            ModelParameter::StomachDecreaseFromFlaSizeAmount => {
+                "stomach_decrease_from_fla_size_amount"
+            }
+            ModelParameter::BaseSpeed => "base_speed",
+            ModelParameter::FlagellumSpeedMultiplier => "flagellum_speed_multiplier",
+            ModelParameter::SizeSpeedMultiplier => "size_speed_multiplier",
+            ModelParameter::SizeEatingDistanceMultiplier => "size_eating_distance_multiplier",
+            ModelParameter::FlagellumSizeEatingDistanceMultiplier => {
+                "flagellum_size_eating_distance_multiplier"
+            }
+            ModelParameter::ReproductionDistance => "reproduction_distance",
+            ModelParameter::ChildDevelopmentFoodDecrease => "child_development_food_decrease",
+            ModelParameter::BirthChanceMultiplier => "birth_chance_multiplier",
+        }
+    }
+
+    pub fn get(self, model: &ModelParameters) -> f64 {
+        match self {
+            ModelParameter::RotationChanceChange => model.rotation_chance_change,
+            ModelParameter::StomachIncreaseFromFoodAmount => {
+                model.stomach_increase_from_food_amount
+            }
+            ModelParameter::StomachDecreaseFromSizeAmount => {
+                model.stomach_decrease_from_size_amount
+            }
+            ModelParameter::StomachDecreaseFromFlaSizeAmount => {
+                model.stomach_decrease_from_fla_size_amount
+            }
+            ModelParameter::BaseSpeed => model.base_speed,
+            ModelParameter::FlagellumSpeedMultiplier => model.flagellum_speed_multiplier,
+            ModelParameter::SizeSpeedMultiplier => model.size_speed_multiplier,
+            ModelParameter::SizeEatingDistanceMultiplier => model.size_eating_distance_multiplier,
+            ModelParameter::FlagellumSizeEatingDistanceMultiplier => {
+                model.flagellum_size_eating_distance_multiplier
+            }
+            ModelParameter::ReproductionDistance => model.reproduction_distance as f64,
+            ModelParameter::ChildDevelopmentFoodDecrease => model.child_development_food_decrease,
+            ModelParameter::BirthChanceMultiplier => model.birth_chance_multiplier,
+        }
+    }
+
+    // the reproduction distance is rounded, and kept at 1 or more
+    pub fn set(self, model: &mut ModelParameters, value: f64) {
+        match self {
+            ModelParameter::RotationChanceChange => model.rotation_chance_change = value,
+            ModelParameter::StomachIncreaseFromFoodAmount => {
+                model.stomach_increase_from_food_amount = value
+            }
+            ModelParameter::StomachDecreaseFromSizeAmount => {
+                model.stomach_decrease_from_size_amount = value
+            }
+            ModelParameter::StomachDecreaseFromFlaSizeAmount => {
+                model.stomach_decrease_from_fla_size_amount = value
+            }
+            ModelParameter::BaseSpeed => model.base_speed = value,
+            ModelParameter::FlagellumSpeedMultiplier => model.flagellum_speed_multiplier = value,
+            ModelParameter::SizeSpeedMultiplier => model.size_speed_multiplier = value,
+            ModelParameter::SizeEatingDistanceMultiplier => {
+                model.size_eating_distance_multiplier = value
+            }
+            ModelParameter::FlagellumSizeEatingDistanceMultiplier => {
+                model.flagellum_size_eating_distance_multiplier = value
+            }
+            ModelParameter::ReproductionDistance => {
+                model.reproduction_distance = value.round().max(1.0) as u32
+            }
+            ModelParameter::ChildDevelopmentFoodDecrease => {
+                model.child_development_food_decrease = value
+            }
+            ModelParameter::BirthChanceMultiplier => model.birth_chance_multiplier = value,
+        }
+    }
+}
+
+impl FromStr for ModelParameter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ModelParameter::ALL
+            .iter()
+            .copied()
+            .find(|parameter| parameter.id() == s)
+            .ok_or_else(|| {
+                let ids: Vec<&str> = ModelParameter::ALL
+                    .iter()
+                    .map(|parameter| parameter.id())
+                    .collect();
+                format!(
+                    "unknown model parameter `{}` (expected one of {})",
+                    s,
+                    ids.join(", ")
+                )
+            })
+    }
+}
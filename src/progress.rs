@@ -0,0 +1,53 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// Told how far along a long running experiment is. Any `FnMut(u32)` closure is an observer.
+pub trait ProgressObserver {
+    // called with the completion percentage whenever it reaches a new whole percent
+    fn on_progress(&mut self, percent: u32);
+}
+
+impl<F: FnMut(u32)> ProgressObserver for F {
+    fn on_progress(&mut self, percent: u32) {
+        self(percent)
+    }
+}
+
+// Asks a running experiment to stop. Cancellation is cooperative: the experiment checks the
+// token between steps and returns `Err(Cancelled)` as soon as it notices. Clones share the same
+// flag, so one can be handed to another thread (or a progress observer) to cancel from there.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the experiment was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
@@ -1,24 +1,38 @@
-// use once_cell::unsync::Lazy;
-// use rand::{Rng, SeedableRng};
-// use rand_xoshiro::Xoshiro256Plus;
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256Plus;
+use serde::{Deserialize, Serialize};
 
-// chosen at random https://xkcd.com/221/
-// const RANDOM_SEED: u64 = 23575;
+// Every simulator owns its own seeded random number generator, so two simulators
+// created with the same seed will always follow the exact same trajectory.
+#[derive(Serialize, Deserialize)]
+pub struct Random {
+    rng: Xoshiro256Plus,
+}
 
-// To do this quickly, we have to use unsafe. Be careful!
-// static mut RANDOM: Lazy<Xoshiro256Plus> = Lazy::new(|| Xoshiro256Plus::seed_from_u64(RANDOM_SEED));
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Xoshiro256Plus::seed_from_u64(seed),
+        }
+    }
 
-// pub fn random_float() -> f64 {
-//     unsafe { RANDOM.gen::<f64>() }
-// }
+    pub fn random_float(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
 
-// TODO: this could be improved by using a seeded random like the above.
-//       after doing performance testing, the above doesn't provide a significant benefit, and it loses some scientific accuracy
+    pub fn random(&mut self, max: u32) -> u32 {
+        (self.random_float() * max as f64) as u32
+    }
 
-pub fn random_float() -> f64 {
-    js_sys::Math::random()
+    // standard normal, using the Box-Muller transform
+    pub fn random_normal(&mut self) -> f64 {
+        // 1 - x is never 0, so the log is finite
+        let radius = (-2.0 * (1.0 - self.random_float()).ln()).sqrt();
+        radius * (2.0 * std::f64::consts::PI * self.random_float()).cos()
+    }
 }
 
-pub fn random(max: u32) -> u32 {
-    (random_float() * max as f64) as u32
+// used when no seed is provided, so that separate runs still differ by default
+pub fn random_seed() -> u64 {
+    rand::random()
 }
@@ -0,0 +1,218 @@
+use std::io::{self, Write};
+
+use crate::experiment::Metric;
+use crate::genes::Gene;
+use crate::simulator::{Simulator, SimulatorConfig};
+
+// Where a recorder's rows go. Every row is one line of CSV or JSON, without the line break.
+pub trait Sink {
+    fn write_row(&mut self, row: &str) -> io::Result<()>;
+}
+
+// collects everything in memory
+impl Sink for String {
+    fn write_row(&mut self, row: &str) -> io::Result<()> {
+        self.push_str(row);
+        self.push('\n');
+        Ok(())
+    }
+}
+
+// Streams rows into a file, stdout, a socket, ... Wrap files in a `BufWriter`, and call
+// `Recorder::flush` (or drop the recorder) once done.
+pub struct WriteSink<W: Write>(pub W);
+
+impl<W: Write> Sink for WriteSink<W> {
+    fn write_row(&mut self, row: &str) -> io::Result<()> {
+        writeln!(self.0, "{}", row)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowFormat {
+    // a header, then one row per sample
+    Csv,
+    // one JSON object per sample (NDJSON), keyed by `step` and the metric names
+    Ndjson,
+}
+
+// One column of a row written with `Recorder::write_row`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    // steps, counts, ...
+    Integer(u64),
+    Number(f64),
+    Text(String),
+    // nested objects in NDJSON rows, CSV writes it as JSON text
+    Json(serde_json::Value),
+}
+
+impl Field {
+    fn csv(&self) -> String {
+        match self {
+            Field::Integer(integer) => integer.to_string(),
+            Field::Number(number) => number.to_string(),
+            Field::Text(text) => text.clone(),
+            Field::Json(json) => json.to_string(),
+        }
+    }
+
+    // NaN isn't valid JSON, serde_json writes it as `null`
+    fn json(&self) -> serde_json::Result<String> {
+        match self {
+            Field::Integer(integer) => serde_json::to_string(integer),
+            Field::Number(number) => serde_json::to_string(number),
+            Field::Text(text) => serde_json::to_string(text),
+            Field::Json(json) => serde_json::to_string(json),
+        }
+    }
+}
+
+type MetricFn = Box<dyn Fn(&Simulator) -> f64>;
+
+// Samples metrics of a simulator every `interval` steps and streams them to a sink, so long runs
+// never hold their whole output in memory. For CSV the header is written with the first row.
+// Rows worked out somewhere else (like the spread of an experiment's replicates) can be streamed
+// the same way with `write_row`.
+pub struct Recorder<S: Sink> {
+    interval: u32,
+    format: RowFormat,
+    metrics: Vec<(String, MetricFn)>,
+    sink: S,
+    started: bool,
+}
+
+impl<S: Sink> Recorder<S> {
+    pub fn new(interval: u32, sink: S) -> Self {
+        assert!(interval > 0, "recording interval must be positive");
+        Self {
+            interval,
+            format: RowFormat::Csv,
+            metrics: Vec::new(),
+            sink,
+            started: false,
+        }
+    }
+
+    // records population size, % food available and the means of the genes `config` has
+    pub fn with_builtin_metrics(interval: u32, sink: S, config: &SimulatorConfig) -> Self {
+        let mut recorder = Self::new(interval, sink);
+        recorder
+            .add_builtin(Metric::PopulationSize)
+            .add_builtin(Metric::FoodAvailable);
+        for gene in Gene::enabled(config) {
+            recorder.add_builtin(Metric::GeneMean(gene));
+        }
+        recorder
+    }
+
+    pub fn set_format(&mut self, format: RowFormat) -> &mut Self {
+        assert!(
+            !self.started,
+            "the format has to be set before recording starts"
+        );
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> RowFormat {
+        self.format
+    }
+
+    pub fn add_builtin(&mut self, metric: Metric) -> &mut Self {
+        self.add_metric(&metric.name(), move |simulator| metric.measure(simulator))
+    }
+
+    // Adds a column. Metrics can't be added once rows have been written, since the header
+    // is already out.
+    pub fn add_metric(
+        &mut self,
+        name: &str,
+        measure: impl Fn(&Simulator) -> f64 + 'static,
+    ) -> &mut Self {
+        assert!(
+            !self.started,
+            "metrics have to be added before recording starts"
+        );
+        self.metrics.push((name.to_string(), Box::new(measure)));
+        self
+    }
+
+    pub fn metric_names(&self) -> impl Iterator<Item = &str> {
+        self.metrics.iter().map(|(name, _)| name.as_str())
+    }
+
+    // Call after every step, writes a row whenever the simulator reaches a multiple of
+    // `interval` steps.
+    pub fn observe(&mut self, simulator: &Simulator) -> io::Result<()> {
+        if simulator.get_steps().is_multiple_of(self.interval) {
+            self.record(simulator)?;
+        }
+        Ok(())
+    }
+
+    // writes a row for the simulator as it is now
+    pub fn record(&mut self, simulator: &Simulator) -> io::Result<()> {
+        let step = match self.format {
+            RowFormat::Csv => "Step #",
+            RowFormat::Ndjson => "step",
+        };
+        let mut row = vec![(
+            step.to_string(),
+            Field::Integer(simulator.get_steps() as u64),
+        )];
+        for (name, measure) in &self.metrics {
+            row.push((name.clone(), Field::Number(measure(simulator))));
+        }
+        self.write_row(&row)
+    }
+
+    // Writes a row of named columns. For CSV the names of the first row become the header, so
+    // every row should have the same columns.
+    pub fn write_row(&mut self, row: &[(String, Field)]) -> io::Result<()> {
+        let first_row = !self.started;
+        self.started = true;
+
+        if self.format == RowFormat::Ndjson {
+            let mut line = String::from("{");
+            for (index, (name, field)) in row.iter().enumerate() {
+                if index > 0 {
+                    line.push(',');
+                }
+                line += &format!("{}:{}", serde_json::to_string(name)?, field.json()?);
+            }
+            line.push('}');
+            return self.sink.write_row(&line);
+        }
+
+        if first_row {
+            let header: Vec<&str> = row.iter().map(|(name, _)| name.as_str()).collect();
+            self.sink.write_row(&header.join(","))?;
+        }
+        let values: Vec<String> = row.iter().map(|(_, field)| field.csv()).collect();
+        self.sink.write_row(&values.join(","))
+    }
+
+    // steps the simulator `steps` times, observing after every step
+    pub fn run(&mut self, simulator: &mut Simulator, steps: u32) -> io::Result<()> {
+        for _ in 0..steps {
+            simulator.simulate_step();
+            self.observe(simulator)?;
+        }
+        Ok(())
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
+
+impl<W: Write> Recorder<WriteSink<W>> {
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.0.flush()
+    }
+}
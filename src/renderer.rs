@@ -1,7 +1,9 @@
-use wasm_bindgen::prelude::*;
+use std::f64::consts::PI;
+
 use web_sys::CanvasRenderingContext2d;
 
-use crate::get_simulator;
+use crate::genes::Gene;
+use crate::simulator::Simulator;
 
 fn clear_canvas(context: &CanvasRenderingContext2d) {
     let canvas = context.canvas().unwrap();
@@ -9,28 +11,27 @@ fn clear_canvas(context: &CanvasRenderingContext2d) {
     context.fill_rect(0.0, 0.0, canvas.width().into(), canvas.height().into());
 }
 
-#[allow(dead_code)]
-#[wasm_bindgen]
-pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera_y: i32) {
-    clear_canvas(&context);
-
-    let simulator = get_simulator();
+pub fn render_simulator(
+    simulator: &Simulator,
+    context: &CanvasRenderingContext2d,
+    camera_x: i32,
+    camera_y: i32,
+) {
+    clear_canvas(context);
 
     // draw food
     context.set_fill_style(&"rgba(58, 29, 0, 0.5)".into());
     for food_row in simulator.get_food() {
-        for food in food_row {
-            if let Some(food) = food {
-                let x = (food.x as i32 + camera_x) as f64;
-                let y = (food.y as i32 + camera_y) as f64;
-                let size = 2.5;
-                context.save();
-                context.begin_path();
-                context.arc(x, y, size, 0.0, 2.0 * 3.14).unwrap();
-                context.fill();
-                context.close_path();
-                context.restore();
-            }
+        for food in food_row.iter().flatten() {
+            let x = (food.x as i32 + camera_x) as f64;
+            let y = (food.y as i32 + camera_y) as f64;
+            let size = 2.5;
+            context.save();
+            context.begin_path();
+            context.arc(x, y, size, 0.0, 2.0 * PI).unwrap();
+            context.fill();
+            context.close_path();
+            context.restore();
         }
     }
 
@@ -38,7 +39,7 @@ pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera
     context.set_line_width(3.0);
     for cell in simulator.get_cells() {
         // make sure cell is in bounds, if not skip rendering
-        let cell_camera_dist = cell.genes.size as i32 + 10;
+        let cell_camera_dist = cell.genes[Gene::SIZE] as i32 + 10;
         if (cell.x as i32 + cell_camera_dist) < -camera_x
             || (cell.y as i32 + cell_camera_dist) < -camera_y
         {
@@ -49,7 +50,7 @@ pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera
         if cell.remaining_steps_until_child_born != 0 {
             // if holding a child, change fill color
             let amount_close_to_birth = (cell.remaining_steps_until_child_born as f64)
-                / cell.genes.steps_until_child_born
+                / cell.genes[Gene::GESTATION_STEPS]
                 * 200.0;
             context.set_fill_style(
                 &format!(
@@ -65,12 +66,12 @@ pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera
         }
         let x = (cell.x as i32 + camera_x) as f64;
         let y = (cell.y as i32 + camera_y) as f64;
-        let size = (cell.genes.size) as f64;
+        let size = cell.genes[Gene::SIZE];
         context.save();
         context.begin_path();
         context.translate(x, y).unwrap();
-        context.rotate(cell.radians - 3.14).unwrap();
-        context.arc(0.0, 0.0, size, 0.2 * 3.14, 1.8 * 3.14).unwrap();
+        context.rotate(cell.radians - PI).unwrap();
+        context.arc(0.0, 0.0, size, 0.2 * PI, 1.8 * PI).unwrap();
         context.translate(0.0, -size * 1.0 / 2.0).unwrap();
         context.line_to(size + 7.0, 0.0);
         context.translate(0.0, size).unwrap();
@@ -89,7 +90,7 @@ pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera
         );
         context.begin_path();
         context
-            .arc(x, y, cell.genes.stomach_size, 0.0, 2.0 * 3.14)
+            .arc(x, y, cell.genes[Gene::STOMACH_SIZE], 0.0, 2.0 * PI)
             .unwrap();
         context.fill();
         context.stroke();
@@ -98,7 +99,7 @@ pub fn render_simulator(context: CanvasRenderingContext2d, camera_x: i32, camera
         context.set_line_width(3.0);
         context.set_stroke_style(&"rgb(50, 200, 255)".into());
         context.begin_path();
-        let fla_radius = (size / 2.0) * cell.genes.flagellum_size;
+        let fla_radius = (size / 2.0) * cell.genes[Gene::FLAGELLUM_SIZE];
         let angle = 3.15f64
             * ((cell.display_seed * 360.0 + simulator.get_steps() as f64) * 30.0 % 360.0)
             / 180.0;
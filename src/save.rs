@@ -0,0 +1,76 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulator::Simulator;
+
+// Bump this whenever the saved representation of the simulator changes, so that old saves
+// are rejected with a clear error instead of being loaded incorrectly.
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SaveFile<'a> {
+    version: u32,
+    simulator: &'a Simulator,
+}
+
+#[derive(Deserialize)]
+struct SaveFileVersion {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct LoadedSaveFile {
+    simulator: Simulator,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    UnsupportedVersion(u32),
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "save format version {} is not supported (expected version {})",
+                version, SAVE_FORMAT_VERSION
+            ),
+            LoadError::Invalid(err) => write!(f, "invalid save file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(err: serde_json::Error) -> Self {
+        LoadError::Invalid(err)
+    }
+}
+
+impl Simulator {
+    // Serializes the complete state of the simulator, including its random number generator,
+    // so a loaded simulator continues exactly where this one left off.
+    pub fn save(&self) -> String {
+        serde_json::to_string(&SaveFile {
+            version: SAVE_FORMAT_VERSION,
+            simulator: self,
+        })
+        .expect("simulator state should always be serializable")
+    }
+
+    pub fn load(data: &str) -> Result<Self, LoadError> {
+        let SaveFileVersion { version } = serde_json::from_str(data)?;
+        if version != SAVE_FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let LoadedSaveFile { mut simulator } = serde_json::from_str(data)?;
+        // derived state isn't saved, so it has to be recreated
+        simulator.rebuild_spatial_index();
+        Ok(simulator)
+    }
+}
@@ -0,0 +1,226 @@
+use crate::experiment::{self, Experiment, Metric, Summary};
+use crate::model::ModelParameter;
+use crate::progress::{CancellationToken, Cancelled, ProgressObserver};
+use crate::randoms::Random;
+
+// Morris elementary effects screening of the model parameters. Each trajectory starts at a
+// random point of a grid over the parameters' ranges and moves one parameter at a time, so
+// every move shows the effect of that parameter alone. Over several trajectories this ranks
+// the parameters by how much they drive each metric (`mu_star`) and how much that depends on
+// the other parameters or is nonlinear (`sigma`), for `trajectories * (parameters + 1)`
+// experiments in total.
+#[derive(Clone)]
+pub struct Morris {
+    // every point is run as this experiment, with its model parameters changed. Like a sweep,
+    // all points use the same seeds.
+    pub experiment: Experiment,
+    pub trajectories: usize,
+    // the grid has this many values per parameter, has to be even
+    pub levels: usize,
+    parameters: Vec<(ModelParameter, f64, f64)>,
+}
+
+impl Morris {
+    pub fn new(experiment: Experiment) -> Self {
+        Self {
+            experiment,
+            trajectories: 10,
+            levels: 4,
+            parameters: Vec::new(),
+        }
+    }
+
+    // varying a parameter again replaces its range
+    pub fn vary(&mut self, parameter: ModelParameter, low: f64, high: f64) -> &mut Self {
+        let range = (parameter, low.min(high), low.max(high));
+        match self
+            .parameters
+            .iter_mut()
+            .find(|(other, _, _)| *other == parameter)
+        {
+            Some(existing) => *existing = range,
+            None => self.parameters.push(range),
+        }
+        self
+    }
+
+    // `spread` either side of the experiment's value, e.g. 0.2 for ±20%
+    pub fn vary_around(&mut self, parameter: ModelParameter, spread: f64) -> &mut Self {
+        let value = parameter.get(&self.experiment.config.model);
+        self.vary(parameter, value * (1.0 - spread), value * (1.0 + spread))
+    }
+
+    // the parameters and their ranges
+    pub fn parameters(&self) -> &[(ModelParameter, f64, f64)] {
+        &self.parameters
+    }
+
+    // The points to run, as fractions of each parameter's range. Trajectory `t` is points
+    // `t * (parameters + 1)` through `(t + 1) * (parameters + 1) - 1`. The design is drawn from
+    // the experiment's seed, so seeded analyses are reproducible.
+    pub fn design(&self) -> Vec<Vec<f64>> {
+        assert!(
+            self.levels >= 2 && self.levels.is_multiple_of(2),
+            "Morris designs need an even number of levels"
+        );
+        let mut rng = Random::new(self.experiment.config.seed);
+        let step = self.delta();
+        let count = self.parameters.len();
+
+        let mut points = Vec::new();
+        for _ in 0..self.trajectories {
+            let mut point: Vec<f64> = (0..count)
+                .map(|_| rng.random(self.levels as u32) as f64 / (self.levels - 1) as f64)
+                .collect();
+            // every parameter moves once, in a random order
+            let mut order: Vec<usize> = (0..count).collect();
+            for i in (1..count).rev() {
+                order.swap(i, rng.random(i as u32 + 1) as usize);
+            }
+
+            points.push(point.clone());
+            for parameter in order {
+                // down when up would leave the range
+                point[parameter] += if point[parameter] + step <= 1.0 + 1e-12 {
+                    step
+                } else {
+                    -step
+                };
+                points.push(point.clone());
+            }
+        }
+        points
+    }
+
+    pub fn run(
+        &self,
+        progress: impl ProgressObserver,
+        cancel: &CancellationToken,
+    ) -> Result<MorrisResults, Cancelled> {
+        let design = self.design();
+        let experiments: Vec<Experiment> = design
+            .iter()
+            .map(|point| {
+                let mut experiment = self.experiment.clone();
+                for (&(parameter, low, high), &fraction) in self.parameters.iter().zip(point) {
+                    parameter.set(&mut experiment.config.model, low + (high - low) * fraction);
+                }
+                experiment
+            })
+            .collect();
+        let results = experiment::run_experiments(&experiments, progress, cancel)?;
+
+        let metrics = self.experiment.metrics.clone();
+        let steps: Vec<u32> = results
+            .first()
+            .map(|results| results.samples.iter().map(|sample| sample.step).collect())
+            .unwrap_or_default();
+        let points_per_trajectory = self.parameters.len() + 1;
+
+        let mut effects = Vec::new();
+        for (sample, &step) in steps.iter().enumerate() {
+            for (metric_index, &metric) in metrics.iter().enumerate() {
+                let value = |point: usize| results[point].samples[sample].mean(metric_index);
+
+                // the elementary effects of each parameter, one per trajectory
+                let mut elementary = vec![Vec::new(); self.parameters.len()];
+                for start in (0..design.len()).step_by(points_per_trajectory) {
+                    for point in start + 1..start + points_per_trajectory {
+                        let (before, after) = (&design[point - 1], &design[point]);
+                        let parameter = (0..self.parameters.len())
+                            .find(|&parameter| before[parameter] != after[parameter])
+                            .expect("every move changes one parameter");
+                        let moved = after[parameter] - before[parameter];
+                        elementary[parameter].push((value(point) - value(point - 1)) / moved);
+                    }
+                }
+
+                for (&(parameter, low, high), effects_of_parameter) in
+                    self.parameters.iter().zip(elementary)
+                {
+                    let signed = Summary::of(effects_of_parameter.iter().copied());
+                    let absolute =
+                        Summary::of(effects_of_parameter.iter().map(|effect| effect.abs()));
+                    effects.push(Effect {
+                        parameter,
+                        low,
+                        high,
+                        step,
+                        metric,
+                        mu: signed.mean,
+                        mu_star: absolute.mean,
+                        sigma: signed.sd,
+                        count: signed.count,
+                    });
+                }
+            }
+        }
+
+        Ok(MorrisResults { effects })
+    }
+
+    // how far every move goes, as a fraction of the range
+    fn delta(&self) -> f64 {
+        self.levels as f64 / (2.0 * (self.levels - 1) as f64)
+    }
+}
+
+// How one parameter affects one metric at one sample step. Effects are the change in the
+// metric's mean across replicates when the parameter goes from `low` to `high`, so parameters
+// with different units can be compared. Moves where the metric was undefined (e.g. gene means
+// of an extinct population) are left out, `count` is the number of moves that were used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Effect {
+    pub parameter: ModelParameter,
+    pub low: f64,
+    pub high: f64,
+    pub step: u32,
+    pub metric: Metric,
+    // the mean effect, positive and negative effects can cancel out
+    pub mu: f64,
+    // the mean absolute effect, the parameter's overall importance
+    pub mu_star: f64,
+    // the spread of the effects, high for interactions and nonlinear effects
+    pub sigma: f64,
+    pub count: usize,
+}
+
+pub struct MorrisResults {
+    // by step, then metric, then parameter
+    pub effects: Vec<Effect>,
+}
+
+impl MorrisResults {
+    // the effects on `metric` at the last sample, most important first
+    pub fn ranking(&self, metric: Metric) -> Vec<Effect> {
+        let last_step = self.effects.iter().map(|effect| effect.step).max();
+        let mut ranking: Vec<Effect> = self
+            .effects
+            .iter()
+            .filter(|effect| Some(effect.step) == last_step && effect.metric == metric)
+            .copied()
+            .collect();
+        ranking.sort_by(|a, b| b.mu_star.total_cmp(&a.mu_star));
+        ranking
+    }
+
+    // a long-format table, one row per step, metric and parameter
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("parameter,low,high,step,metric,mu,mu_star,sigma,n");
+        for effect in &self.effects {
+            csv += &format!(
+                "\n{},{},{},{},{},{},{},{},{}",
+                effect.parameter.id(),
+                effect.low,
+                effect.high,
+                effect.step,
+                effect.metric.id(),
+                effect.mu,
+                effect.mu_star,
+                effect.sigma,
+                effect.count
+            );
+        }
+        csv
+    }
+}
@@ -1,35 +1,44 @@
-use std::collections::HashMap;
+use std::str::FromStr;
 
-use crate::cell;
+use serde::{Deserialize, Serialize};
+
+use crate::cell::{self, CellId, Father};
+use crate::diploid::{Genotype, Ploidy};
 use crate::food;
-use crate::genes::Genes;
-use crate::randoms::random_float;
+use crate::genealogy::Genealogy;
+use crate::genes::{GeneFractions, GeneRates, Genes, MutationKernel};
+use crate::mating::{MatingTypes, FEMALE};
+use crate::model::ModelParameters;
+use crate::randoms::{random_seed, Random};
+use crate::spatial::SpatialGrid;
+use crate::timeline::Timeline;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize, Deserialize)]
 pub struct Simulator {
     config: SimulatorConfig,
     steps: u32,
+    // always sorted by id, since ids are handed out in increasing order and cells are only
+    // ever appended
     cells: Vec<cell::Cell>,
+    next_cell_id: u64,
     food: Vec<Vec<Option<food::Food>>>,
+    rng: Random,
+    genealogy: Genealogy,
+    timeline: Timeline,
+    extinction_step: Option<u32>,
+    // positions of all cells, kept up to date as cells move
+    #[serde(skip)]
+    spatial_index: SpatialGrid<CellId>,
 }
 
 impl Simulator {
     pub fn new(repro_method: Reproduction) -> Self {
-        let config = SimulatorConfig::new(repro_method);
-
-        let cells = (0..config.cell_number)
-            .map(|_| {
-                cell::Cell::new(
-                    Genes::new(repro_method),
-                    cell::Cell::get_random_pos_in_bounds(&config),
-                    cell::Cell::get_random_pos_in_bounds(&config),
-                    5.0,
-                    &config,
-                )
-            })
-            .collect();
-
+        Self::with_config(SimulatorConfig::new(repro_method))
+    }
+    pub fn with_config(config: SimulatorConfig) -> Self {
         let food = (0..config.width / config.food_spacing)
             .map(|_| {
                 (0..config.height / config.food_spacing)
@@ -39,11 +48,36 @@ impl Simulator {
             .collect();
 
         let mut new_self = Self {
-            cells,
+            cells: Vec::new(),
+            next_cell_id: 0,
             food,
             config,
             steps: 0,
+            rng: Random::new(config.seed),
+            genealogy: Genealogy::default(),
+            timeline: Timeline::default(),
+            extinction_step: None,
+            spatial_index: SpatialGrid::default(),
         };
+        new_self.rebuild_spatial_index();
+
+        for _ in 0..config.cell_number {
+            let rng = &mut new_self.rng;
+            let genotype = match config.ploidy {
+                Ploidy::Haploid => None,
+                Ploidy::Diploid => Some(Genotype::new(&config, rng)),
+            };
+            let genes = match &genotype {
+                Some(genotype) => genotype.phenotype(&config),
+                None => Genes::new(&config, rng),
+            };
+            let x = cell::Cell::get_random_pos_in_bounds(&config, rng);
+            let y = cell::Cell::get_random_pos_in_bounds(&config, rng);
+            let mut new_cell = cell::Cell::new(genes, x, y, 5.0, &config, rng);
+            new_cell.genotype = genotype;
+            new_cell.mating_type = config.mating_types.draw(rng);
+            new_self.add_cell(new_cell);
+        }
         new_self.fill_food();
 
         new_self
@@ -63,73 +97,237 @@ impl Simulator {
     pub fn get_food(&self) -> &Vec<Vec<Option<food::Food>>> {
         &self.food
     }
+    pub fn get_genealogy(&self) -> &Genealogy {
+        &self.genealogy
+    }
+    // the step the last cell died on
+    pub fn get_extinction_step(&self) -> Option<u32> {
+        self.extinction_step
+    }
+    pub fn is_extinct(&self) -> bool {
+        self.extinction_step.is_some()
+    }
+    pub fn get_timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+    // replaces the scheduled environmental changes, any that have already started take effect
+    // right away
+    pub fn set_timeline(&mut self, timeline: Timeline) {
+        self.timeline = timeline;
+        self.timeline.catch_up(self.steps, &mut self.config);
+    }
+    pub fn get_phylogeny_newick(&self) -> String {
+        self.genealogy.to_newick(self.steps, &self.config)
+    }
+
+    pub fn get_cell(&self, id: CellId) -> Option<&cell::Cell> {
+        self.get_cell_index(id).map(|index| &self.cells[index])
+    }
+    pub fn get_cell_index(&self, id: CellId) -> Option<usize> {
+        self.cells.binary_search_by_key(&id, |cell| cell.id()).ok()
+    }
 
-    pub fn simulate_step(&mut self) {
+    // all living cells within `radius` of (x, y)
+    pub fn cells_within(&self, x: u32, y: u32, radius: f64) -> Vec<CellId> {
+        let mut found = self.spatial_index.query_radius(x, y, radius);
+        found.retain(|&id| self.is_alive(id));
+        found
+    }
+
+    // the living cell closest to (x, y), ignoring `exclude`
+    pub fn nearest_cell(&self, x: u32, y: u32, exclude: Option<CellId>) -> Option<CellId> {
+        self.spatial_index
+            .nearest(x, y, |id| Some(id) != exclude && self.is_alive(id))
+    }
+
+    fn is_alive(&self, id: CellId) -> bool {
+        self.get_cell(id).is_some_and(|cell| cell.alive)
+    }
+
+    pub(crate) fn rebuild_spatial_index(&mut self) {
+        self.spatial_index = SpatialGrid::new(
+            self.config.width,
+            self.config.height,
+            SPATIAL_INDEX_BUCKET_SIZE,
+        );
+        for cell in self.cells.iter() {
+            self.spatial_index.insert(cell.id(), cell.x, cell.y);
+        }
+    }
+
+    // Returns the extinction on the step the last cell dies. From then on the world is frozen,
+    // only the step count (and the timeline) keep going, so extinct replicates are cheap.
+    pub fn simulate_step(&mut self) -> Option<Extinction> {
         self.steps += 1;
 
+        if self.extinction_step.is_some() {
+            self.timeline.apply(self.steps, &mut self.config);
+            return None;
+        }
+
         self.add_food();
 
         let food_spacing = self.config.food_spacing as f64;
 
         // cell death works by storing all the dead cells after looping,
-        // then removing them from the list at the end
-        let mut i = 0;
+        // then removing them from the list at the end in one pass
         let mut indexes_to_remove: Vec<usize> = Vec::new();
 
-        let mut reproduction_locations: HashMap<(u32, u32), usize> = HashMap::new();
+        // (cell index, mating type) of the cells waiting for a mate in each box
+        let reproduction_distance = self.config.model.reproduction_distance.max(1);
+        let mut waiting_mates: SpatialGrid<(usize, u32)> = SpatialGrid::new(
+            self.config.width.saturating_add(reproduction_distance),
+            self.config.height.saturating_add(reproduction_distance),
+            reproduction_distance,
+        );
 
         for cell_index in 0..self.cells.len() {
-            i += 1;
-
             // to access the current cell, use `&mut self.cells[cell_index]`
             // this is necessary to avoid multiple mutable borrows
             // see https://www.reddit.com/r/rust/comments/y1qefw/question_avoiding_cannot_borrow_as_mutable_more/
             // perf impact should be very low because it is just a vec lookup
 
             // if not alive, skip simulating
-            if !(&self.cells[cell_index]).alive {
-                indexes_to_remove.push(i - 1);
+            if !self.cells[cell_index].alive {
+                indexes_to_remove.push(cell_index);
                 continue;
             }
 
-            (&mut self.cells[cell_index]).find_food_and_eat(&mut self.food, food_spacing);
+            self.cells[cell_index].find_food_and_eat(
+                &mut self.food,
+                food_spacing,
+                &self.config.model,
+            );
 
             // reproduction
             match self.config.reproduction {
                 Reproduction::Asexual => {
                     // if asexual then reproduce if possible
-                    let cell = &mut self.cells[cell_index];
-                    if cell.reproduction_cooldown == 0 {
-                        cell.start_reproduction(&cell.genes.clone(), &self.config);
+                    if self.cells[cell_index].reproduction_cooldown == 0 {
+                        self.impregnate(cell_index, None);
                     }
                 }
                 Reproduction::Sexual => {
-                    let genes = self.cells[cell_index].genes;
-                    if let Some(index_to_impregnate) = (&mut self.cells[cell_index])
-                        .find_mate_and_reproduce(
-                            cell_index,
-                            &mut reproduction_locations,
-                            &self.config,
-                        )
-                    {
-                        let cell_to_impregnate = &mut self.cells[index_to_impregnate];
-                        cell_to_impregnate.start_reproduction(&genes, &self.config);
+                    if let Some(mate_index) = self.find_mate(cell_index, &mut waiting_mates) {
+                        self.cells[cell_index].reproduction_cooldown =
+                            self.config.reproduction_cooldown;
+
+                        // the cell that was waiting carries the child, unless it's the male
+                        let (mother, father) = if self.config.mating_types == MatingTypes::Sexes
+                            && self.cells[cell_index].mating_type == Some(FEMALE)
+                        {
+                            (cell_index, mate_index)
+                        } else {
+                            (mate_index, cell_index)
+                        };
+                        let father = self.cells[father].as_father();
+                        self.impregnate(mother, Some(&father));
                     }
                 }
             }
 
-            let possibly_new_cell = (&mut self.cells[cell_index]).simulate_step(&self.config);
+            let cell = &mut self.cells[cell_index];
+            let (old_x, old_y) = (cell.x, cell.y);
+            let father = cell.child_father();
+            let possibly_new_cell = cell.simulate_step(&self.config, &mut self.rng);
+            let (new_x, new_y) = (cell.x, cell.y);
+            let mother = cell.id();
+            // the carried child was either born or failed to be born this step
+            let conception_ended = father.is_some() && cell.child_father().is_none();
+            let died = !cell.alive;
+
+            self.spatial_index
+                .update(mother, old_x, old_y, new_x, new_y);
             if let Some(new_cell) = possibly_new_cell {
-                self.cells.push(new_cell);
+                self.add_child(new_cell, Some(mother), father);
+            }
+            if let (true, Some(father)) = (conception_ended, father) {
+                self.genealogy.record_conception_ended(father);
+            }
+            if died {
+                self.genealogy.record_death(mother, self.steps);
             }
         }
 
-        // after removing food we need to adjust all the indexes after -- so we do this hack
-        i = 0;
-        for index in indexes_to_remove {
-            self.cells.remove(index - i);
-            i += 1;
+        self.remove_cells(indexes_to_remove);
+
+        // so the config holds the values the next step will use
+        self.timeline.apply(self.steps, &mut self.config);
+
+        if !self.cells.iter().any(|cell| cell.alive) {
+            // nothing will ever happen again, so the last dead cells don't need to linger
+            self.remove_cells((0..self.cells.len()).collect());
+            self.extinction_step = Some(self.steps);
+            return Some(Extinction { step: self.steps });
         }
+        None
+    }
+
+    // Cell reproduction works by boxes the size of the reproduction distance. The first ready
+    // cell of each mating type to reach a box on a step waits there, and every compatible ready
+    // cell reaching the same box later that step mates with it. With mating types, the waiting
+    // cell of the lowest compatible type is picked.
+    fn find_mate(
+        &self,
+        cell_index: usize,
+        waiting_mates: &mut SpatialGrid<(usize, u32)>,
+    ) -> Option<usize> {
+        let cell = &self.cells[cell_index];
+        if cell.reproduction_cooldown != 0 {
+            return None;
+        }
+
+        let reproduction_distance = self.config.model.reproduction_distance.max(1);
+        let x = mating_box_position(cell.x, reproduction_distance);
+        let y = mating_box_position(cell.y, reproduction_distance);
+        let mating_type = cell.mating_type.unwrap_or(0);
+        let mate = waiting_mates
+            .bucket(x, y)
+            .filter(|&(_, other_type)| {
+                self.config
+                    .mating_types
+                    .can_mate(Some(mating_type), Some(other_type))
+            })
+            .min_by_key(|&(_, other_type)| other_type);
+        if let Some((mate_index, _)) = mate {
+            return Some(mate_index);
+        }
+
+        if waiting_mates
+            .bucket(x, y)
+            .all(|(_, other_type)| other_type != mating_type)
+        {
+            waiting_mates.insert((cell_index, mating_type), x, y);
+        }
+        None
+    }
+
+    // `indexes` must be sorted
+    fn remove_cells(&mut self, indexes: Vec<usize>) {
+        if indexes.is_empty() {
+            return;
+        }
+
+        for &index in &indexes {
+            let cell = &self.cells[index];
+            self.spatial_index.remove(cell.id(), cell.x, cell.y);
+            // a child carried by a dead cell will never be born
+            if let Some(father) = cell.child_father() {
+                self.genealogy.record_conception_ended(father);
+            }
+        }
+
+        // `indexes` is sorted, so we can walk it alongside the cells
+        let mut indexes = indexes.into_iter().peekable();
+        let mut index = 0;
+        self.cells.retain(|_| {
+            let remove = indexes.peek() == Some(&index);
+            if remove {
+                indexes.next();
+            }
+            index += 1;
+            !remove
+        });
     }
 
     fn fill_food(&mut self) {
@@ -166,7 +364,7 @@ impl Simulator {
                 .get_mut(row as usize)
                 .expect("food row did not exist");
             for col in 0..height / food_spacing {
-                if food_row[col as usize].is_none() && random_float() < spawn_chance {
+                if food_row[col as usize].is_none() && self.rng.random_float() < spawn_chance {
                     food_row[col as usize] = Some(food::Food::new(
                         row * food_spacing + food_offset,
                         col * food_spacing + food_offset,
@@ -176,12 +374,65 @@ impl Simulator {
         }
     }
 
-    pub fn add_cell(&mut self, new_cell: cell::Cell) {
+    // gives the cell a new id and adds it to the simulation
+    fn impregnate(&mut self, index: usize, father: Option<&Father>) {
+        let cell = &mut self.cells[index];
+        // a new conception replaces the child the cell might already be carrying
+        let previous_father = cell.child_father();
+        cell.start_reproduction(father, &self.config, &mut self.rng);
+
+        if let Some(father) = father {
+            self.genealogy.record_conception(father.id);
+        }
+        if let Some(previous_father) = previous_father {
+            self.genealogy.record_conception_ended(previous_father);
+        }
+    }
+
+    // gives the cell a new id and adds it to the simulation as the founder of a new lineage
+    pub fn add_cell(&mut self, new_cell: cell::Cell) -> CellId {
+        self.add_child(new_cell, None, None)
+    }
+
+    fn add_child(
+        &mut self,
+        mut new_cell: cell::Cell,
+        mother: Option<CellId>,
+        father: Option<CellId>,
+    ) -> CellId {
+        let id = CellId(self.next_cell_id);
+        self.next_cell_id += 1;
+        new_cell.set_id(id);
+
+        self.genealogy
+            .record_birth(id, mother, father, self.steps, new_cell.genes);
+        self.spatial_index.insert(id, new_cell.x, new_cell.y);
         self.cells.push(new_cell);
+        id
     }
 }
 
-#[derive(Copy, Clone)]
+// Happens once, on the step the last living cell dies
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Extinction {
+    pub step: u32,
+}
+
+// roughly the distance cells reproduce at, so neighbor queries only touch a few buckets
+const SPATIAL_INDEX_BUCKET_SIZE: u32 = 50;
+
+// The mating boxes are offset by one box and were found by rounding towards zero, so the first
+// box spans 1 up to twice the reproduction distance and 0 is a box of its own. This moves a
+// position so the buckets of a grid the size of the reproduction distance line up with them.
+fn mating_box_position(position: u32, reproduction_distance: u32) -> u32 {
+    if position == 0 || position >= reproduction_distance.saturating_mul(2) {
+        position
+    } else {
+        reproduction_distance
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct SimulatorConfig {
     pub reproduction: Reproduction,
     pub food_density: u32,
@@ -192,6 +443,27 @@ pub struct SimulatorConfig {
     pub reproduction_cooldown: u32,
     pub mutation_chance: f64,
     pub mutation_percent_change: f64,
+    // how mutations change genes, see `MutationKernel`
+    pub mutation_kernel: MutationKernel,
+    // multiplies `mutation_chance` per gene
+    pub relative_mutation_rates: GeneRates,
+    // every cell carries its own mutation chance in the `mutation_rate` gene, which is
+    // inherited and mutates like any other gene, instead of all of them using `mutation_chance`
+    pub evolvable_mutation_rate: bool,
+    // one or two alleles of every gene, see `Ploidy`
+    pub ploidy: Ploidy,
+    // Diploid cells only: how far each gene is from the smaller towards the larger allele, so
+    // 0.5 is the mean of both, 1 makes the larger allele dominant and 0 the smaller one
+    pub dominance: GeneFractions,
+    // Diploid cells only: the chance of a gamete switching to the other set of alleles before
+    // each gene (the first gene's is unused). 0.5 is unlinked, lower chances keep a gene
+    // together with the one before it in the registry.
+    pub crossover_map: GeneFractions,
+    // sexual cells only mate with compatible types, see `MatingTypes`
+    pub mating_types: MatingTypes,
+    pub seed: u64,
+    // the constants of how cells behave
+    pub model: ModelParameters,
 }
 
 impl SimulatorConfig {
@@ -206,13 +478,38 @@ impl SimulatorConfig {
             reproduction_cooldown: 200,
             mutation_chance: 0.01,
             mutation_percent_change: 0.1,
+            mutation_kernel: MutationKernel::Fixed,
+            relative_mutation_rates: GeneRates::default(),
+            evolvable_mutation_rate: false,
+            ploidy: Ploidy::Haploid,
+            dominance: GeneFractions::default(),
+            crossover_map: GeneFractions::default(),
+            mating_types: MatingTypes::None,
+            seed: random_seed(),
+            model: ModelParameters::default(),
         }
     }
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Reproduction {
     #[default]
     Asexual,
     Sexual,
 }
+
+impl FromStr for Reproduction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asexual" => Ok(Reproduction::Asexual),
+            "sexual" => Ok(Reproduction::Sexual),
+            _ => Err(format!(
+                "unknown reproduction method `{}` (expected `asexual` or `sexual`)",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{ser, Serialize, Serializer};
+
+use crate::cell::Cell;
+use crate::food::Food;
+use crate::genes::Gene;
+use crate::simulator::{Simulator, SimulatorConfig};
+
+// Everything about the world at one step, in a form that's easy to read from other tools.
+// Unlike a save it leaves out the random number generator and the genealogy, and it can't be
+// loaded back.
+#[derive(Serialize)]
+pub struct Snapshot<'a> {
+    pub step: u32,
+    pub extinction_step: Option<u32>,
+    pub config: &'a SimulatorConfig,
+    // with all their genes and internal state
+    pub cells: Vec<SnapshotCell<'a>>,
+    // only the food that's there to be eaten
+    pub food: Vec<&'a Food>,
+}
+
+// A cell as it's saved, but without the genes its config doesn't have
+pub struct SnapshotCell<'a> {
+    pub cell: &'a Cell,
+    config: &'a SimulatorConfig,
+}
+
+impl Serialize for SnapshotCell<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut cell = serde_json::to_value(self.cell).map_err(ser::Error::custom)?;
+        let disabled: Vec<Gene> = Gene::all()
+            .filter(|gene| !gene.is_enabled(self.config))
+            .collect();
+        let strip = |genes: &mut serde_json::Value| {
+            if let Some(genes) = genes.as_object_mut() {
+                for gene in &disabled {
+                    genes.remove(gene.id());
+                }
+            }
+        };
+        // the genes of the cell, its alleles and those of the child it's carrying
+        for (genes, genotype) in [("genes", "genotype"), ("child_genes", "child_genotype")] {
+            if let Some(genes) = cell.get_mut(genes) {
+                strip(genes);
+            }
+            if let Some(genotype) = cell.get_mut(genotype) {
+                for alleles in ["maternal", "paternal"] {
+                    if let Some(alleles) = genotype.get_mut(alleles) {
+                        strip(alleles);
+                    }
+                }
+            }
+        }
+        cell.serialize(serializer)
+    }
+}
+
+impl Simulator {
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            step: self.get_steps(),
+            extinction_step: self.get_extinction_step(),
+            config: self.get_config(),
+            cells: self
+                .get_cells()
+                .iter()
+                .map(|cell| SnapshotCell {
+                    cell,
+                    config: self.get_config(),
+                })
+                .collect(),
+            food: self.get_food().iter().flatten().flatten().collect(),
+        }
+    }
+
+    pub fn snapshot_json(&self) -> String {
+        serde_json::to_string(&self.snapshot()).expect("snapshots should always be serializable")
+    }
+}
@@ -0,0 +1,187 @@
+// A uniform grid of square buckets over the simulation area. Every entry remembers its
+// position, so radius and nearest neighbor queries only have to look at nearby buckets
+// instead of every cell.
+pub struct SpatialGrid<K> {
+    bucket_size: u32,
+    columns: usize,
+    rows: usize,
+    buckets: Vec<Vec<(K, u32, u32)>>,
+}
+
+impl<K> Default for SpatialGrid<K> {
+    fn default() -> Self {
+        Self {
+            bucket_size: 1,
+            columns: 0,
+            rows: 0,
+            buckets: Vec::new(),
+        }
+    }
+}
+
+impl<K: Copy + PartialEq> SpatialGrid<K> {
+    pub fn new(width: u32, height: u32, bucket_size: u32) -> Self {
+        assert!(bucket_size > 0, "bucket size must be positive");
+        let columns = (width / bucket_size + 1) as usize;
+        let rows = (height / bucket_size + 1) as usize;
+        Self {
+            bucket_size,
+            columns,
+            rows,
+            buckets: (0..columns * rows).map(|_| Vec::new()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(|bucket| bucket.is_empty())
+    }
+
+    pub fn clear(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            bucket.clear();
+        }
+    }
+
+    pub fn insert(&mut self, key: K, x: u32, y: u32) {
+        let bucket = self.bucket_index(x, y);
+        self.buckets[bucket].push((key, x, y));
+    }
+
+    // returns whether the key was found at that position
+    pub fn remove(&mut self, key: K, x: u32, y: u32) -> bool {
+        let index = self.bucket_index(x, y);
+        let bucket = &mut self.buckets[index];
+        if let Some(i) = bucket.iter().position(|(other, _, _)| *other == key) {
+            bucket.swap_remove(i);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn update(&mut self, key: K, old_x: u32, old_y: u32, new_x: u32, new_y: u32) {
+        let old_bucket = self.bucket_index(old_x, old_y);
+        let new_bucket = self.bucket_index(new_x, new_y);
+        if old_bucket == new_bucket {
+            if let Some(entry) = self.buckets[old_bucket]
+                .iter_mut()
+                .find(|(other, _, _)| *other == key)
+            {
+                *entry = (key, new_x, new_y);
+                return;
+            }
+        } else {
+            self.remove(key, old_x, old_y);
+        }
+        self.insert(key, new_x, new_y);
+    }
+
+    // every key within `radius` of (x, y), in no particular order
+    pub fn query_radius(&self, x: u32, y: u32, radius: f64) -> Vec<K> {
+        let mut found = Vec::new();
+        let reach = (radius / self.bucket_size as f64).ceil() as usize;
+        let (column, row) = self.bucket_coords(x, y);
+        let radius_squared = radius * radius;
+
+        for other_row in row.saturating_sub(reach)..(row + reach + 1).min(self.rows) {
+            for other_column in column.saturating_sub(reach)..(column + reach + 1).min(self.columns)
+            {
+                for &(key, other_x, other_y) in
+                    &self.buckets[other_row * self.columns + other_column]
+                {
+                    if distance_squared(x, y, other_x, other_y) <= radius_squared {
+                        found.push(key);
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    // the keys in the bucket holding (x, y), in the order they were inserted if none were removed
+    pub fn bucket(&self, x: u32, y: u32) -> impl Iterator<Item = K> + '_ {
+        self.buckets[self.bucket_index(x, y)]
+            .iter()
+            .map(|&(key, _, _)| key)
+    }
+
+    // the closest key to (x, y) for which `filter` returns true
+    pub fn nearest(&self, x: u32, y: u32, filter: impl Fn(K) -> bool) -> Option<K> {
+        let (column, row) = self.bucket_coords(x, y);
+        let max_ring = self.columns.max(self.rows);
+        let mut best: Option<(K, f64)> = None;
+
+        // search rings of buckets outwards until no closer entry can exist
+        for ring in 0..=max_ring {
+            if let Some((_, best_distance_squared)) = best {
+                let ring_distance = (ring.saturating_sub(1) * self.bucket_size as usize) as f64;
+                if ring_distance * ring_distance > best_distance_squared {
+                    break;
+                }
+            }
+
+            for (other_column, other_row) in ring_coords(column, row, ring) {
+                if other_column >= self.columns || other_row >= self.rows {
+                    continue;
+                }
+                for &(key, other_x, other_y) in
+                    &self.buckets[other_row * self.columns + other_column]
+                {
+                    let dist = distance_squared(x, y, other_x, other_y);
+                    if best.is_none_or(|(_, best_dist)| dist < best_dist) && filter(key) {
+                        best = Some((key, dist));
+                    }
+                }
+            }
+        }
+
+        best.map(|(key, _)| key)
+    }
+
+    fn bucket_coords(&self, x: u32, y: u32) -> (usize, usize) {
+        let column = ((x / self.bucket_size) as usize).min(self.columns.saturating_sub(1));
+        let row = ((y / self.bucket_size) as usize).min(self.rows.saturating_sub(1));
+        (column, row)
+    }
+
+    fn bucket_index(&self, x: u32, y: u32) -> usize {
+        let (column, row) = self.bucket_coords(x, y);
+        row * self.columns + column
+    }
+}
+
+fn distance_squared(x: u32, y: u32, other_x: u32, other_y: u32) -> f64 {
+    let dx = x.abs_diff(other_x) as f64;
+    let dy = y.abs_diff(other_y) as f64;
+    dx * dx + dy * dy
+}
+
+// the bucket coordinates exactly `ring` buckets away (in chessboard distance) from the center
+fn ring_coords(column: usize, row: usize, ring: usize) -> Vec<(usize, usize)> {
+    if ring == 0 {
+        return vec![(column, row)];
+    }
+
+    let ring = ring as isize;
+    let (column, row) = (column as isize, row as isize);
+    let mut coords = Vec::new();
+    for offset in -ring..=ring {
+        coords.push((column + offset, row - ring));
+        coords.push((column + offset, row + ring));
+    }
+    for offset in (-ring + 1)..ring {
+        coords.push((column - ring, row + offset));
+        coords.push((column + ring, row + offset));
+    }
+
+    coords
+        .into_iter()
+        .filter(|&(column, row)| column >= 0 && row >= 0)
+        .map(|(column, row)| (column as usize, row as usize))
+        .collect()
+}
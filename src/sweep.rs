@@ -0,0 +1,220 @@
+use std::io;
+
+use crate::experiment::{self, Experiment, ExperimentResults, Sample};
+use crate::progress::{CancellationToken, Cancelled, ProgressObserver};
+use crate::recorder::{Field, Recorder, RowFormat, Sink};
+use crate::timeline::ConfigField;
+
+// Runs an experiment for every combination of a grid of config values. Each combination gets
+// the same seeds, so differences between them come from the parameters and not from chance.
+// The experiment's timeline can't change the swept fields, or it would override their values.
+#[derive(Clone)]
+pub struct Sweep {
+    pub experiment: Experiment,
+    parameters: Vec<(ConfigField, Vec<f64>)>,
+}
+
+impl Sweep {
+    pub fn new(experiment: Experiment) -> Self {
+        Self {
+            experiment,
+            parameters: Vec::new(),
+        }
+    }
+
+    // varying a field again replaces its values, which have to be within the field's range
+    pub fn vary(&mut self, field: ConfigField, values: Vec<f64>) -> &mut Self {
+        for &value in &values {
+            if let Err(err) = field.check(value) {
+                panic!("{}", err);
+            }
+        }
+        match self
+            .parameters
+            .iter_mut()
+            .find(|(other, _)| *other == field)
+        {
+            Some(parameter) => parameter.1 = values,
+            None => self.parameters.push((field, values)),
+        }
+        self
+    }
+
+    // `start`, `start + step`, ... up to and including `end`
+    pub fn vary_range(&mut self, field: ConfigField, start: f64, end: f64, step: f64) -> &mut Self {
+        assert!(step > 0.0, "sweep step must be positive");
+        assert!(end >= start, "sweep range can't end before it starts");
+        // a little slack so `end` isn't lost to rounding
+        let count = ((end - start) / step + 1e-9).floor() as i64 + 1;
+        let values = (0..count)
+            .map(|i| {
+                let value = start + i as f64 * step;
+                // so 0.1 steps give 0.3 and not 0.30000000000000004
+                (value * 1e12).round() / 1e12
+            })
+            .collect();
+        self.vary(field, values)
+    }
+
+    pub fn parameters(&self) -> &[(ConfigField, Vec<f64>)] {
+        &self.parameters
+    }
+
+    // every combination of values in parameter order, the last parameter changing fastest
+    pub fn combinations(&self) -> Vec<Vec<f64>> {
+        let mut combinations = vec![Vec::new()];
+        for (_, values) in &self.parameters {
+            combinations = combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |&value| {
+                        let mut combination = combination.clone();
+                        combination.push(value);
+                        combination
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+
+    // Writes a sample of `combination` through `recorder`. CSV is a tidy long-format table, one
+    // row per metric, with the swept fields, then `step`, `metric` and the spread of the metric
+    // across replicates. NDJSON lines are like `Sample::write`'s, with the swept values under
+    // `parameters`.
+    pub fn write_sample<S: Sink>(
+        &self,
+        combination: &[f64],
+        sample: &Sample,
+        recorder: &mut Recorder<S>,
+    ) -> io::Result<()> {
+        let metrics = &self.experiment.metrics;
+        if recorder.format() == RowFormat::Ndjson {
+            let parameters: serde_json::Map<String, serde_json::Value> = self
+                .parameters
+                .iter()
+                .zip(combination)
+                .map(|((field, _), value)| (field.id().to_string(), serde_json::json!(value)))
+                .collect();
+            return recorder.write_row(&[
+                ("parameters".to_string(), Field::Json(parameters.into())),
+                ("step".to_string(), Field::Integer(sample.step as u64)),
+                (
+                    "metrics".to_string(),
+                    Field::Json(sample.metrics_json(metrics)),
+                ),
+            ]);
+        }
+
+        for (index, metric) in metrics.iter().enumerate() {
+            let summary = sample.summary(index);
+            let mut row = self.key(combination, sample);
+            row.extend([
+                ("metric".to_string(), Field::Text(metric.id())),
+                ("mean".to_string(), Field::Number(summary.mean)),
+                ("sd".to_string(), Field::Number(summary.sd)),
+                ("se".to_string(), Field::Number(summary.se)),
+                ("ci95_low".to_string(), Field::Number(summary.ci95_low)),
+                ("ci95_high".to_string(), Field::Number(summary.ci95_high)),
+                ("median".to_string(), Field::Number(summary.median)),
+                ("n".to_string(), Field::Integer(summary.count as u64)),
+            ]);
+            recorder.write_row(&row)?;
+        }
+        Ok(())
+    }
+
+    // every replicate's own value, one row per replicate and metric
+    pub fn write_replicates<S: Sink>(
+        &self,
+        combination: &[f64],
+        sample: &Sample,
+        recorder: &mut Recorder<S>,
+    ) -> io::Result<()> {
+        for (replicate, values) in sample.replicates.iter().enumerate() {
+            for (metric, &value) in self.experiment.metrics.iter().zip(values) {
+                let mut row = self.key(combination, sample);
+                row.extend([
+                    ("replicate".to_string(), Field::Integer(replicate as u64)),
+                    ("metric".to_string(), Field::Text(metric.id())),
+                    ("value".to_string(), Field::Number(value)),
+                ]);
+                recorder.write_row(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the swept fields' values and the step at the start of every row
+    fn key(&self, combination: &[f64], sample: &Sample) -> Vec<(String, Field)> {
+        let mut key: Vec<(String, Field)> = self
+            .parameters
+            .iter()
+            .zip(combination)
+            .map(|((field, _), &value)| (field.id().to_string(), Field::Number(value)))
+            .collect();
+        key.push(("step".to_string(), Field::Integer(sample.step as u64)));
+        key
+    }
+
+    pub fn run(
+        &self,
+        progress: impl ProgressObserver,
+        cancel: &CancellationToken,
+    ) -> Result<SweepResults, Cancelled> {
+        self.stream(|_, _| {}, progress, cancel)
+    }
+
+    // Like `run`, also handing every sample to `on_sample` with the values of its combination
+    // as soon as all of the combination's replicates reached it, see `write_sample`
+    pub fn stream(
+        &self,
+        mut on_sample: impl FnMut(&[f64], &Sample),
+        progress: impl ProgressObserver,
+        cancel: &CancellationToken,
+    ) -> Result<SweepResults, Cancelled> {
+        for scheduled in self.experiment.timeline.changes() {
+            assert!(
+                self.parameters
+                    .iter()
+                    .all(|(field, _)| *field != scheduled.field),
+                "swept fields can't also be changed by the timeline"
+            );
+        }
+
+        // rows show the values the experiments actually ran with, e.g. rounded whole numbers
+        let mut combinations = Vec::new();
+        let mut experiments = Vec::new();
+        for combination in self.combinations() {
+            let mut experiment = self.experiment.clone();
+            for ((field, _), &value) in self.parameters.iter().zip(&combination) {
+                field.set(&mut experiment.config, value);
+            }
+            combinations.push(
+                self.parameters
+                    .iter()
+                    .map(|(field, _)| field.get(&experiment.config))
+                    .collect::<Vec<f64>>(),
+            );
+            experiments.push(experiment);
+        }
+
+        Ok(SweepResults {
+            parameters: self.parameters.iter().map(|(field, _)| *field).collect(),
+            results: experiment::stream_experiments(
+                &experiments,
+                |index, sample| on_sample(&combinations[index], sample),
+                progress,
+                cancel,
+            )?,
+            combinations,
+        })
+    }
+}
+
+pub struct SweepResults {
+    pub parameters: Vec<ConfigField>,
+    // the values of `parameters` for each entry of `results`
+    pub combinations: Vec<Vec<f64>>,
+    pub results: Vec<ExperimentResults>,
+}
@@ -0,0 +1,256 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulator::SimulatorConfig;
+
+// The config fields a timeline can change. The layout of the world (its size, food spacing,
+// starting cells and seed) is fixed once it's created, so only fields read every step are here.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigField {
+    FoodDensity,
+    ReproductionCooldown,
+    MutationChance,
+    MutationPercentChange,
+}
+
+impl ConfigField {
+    pub const ALL: [ConfigField; 4] = [
+        ConfigField::FoodDensity,
+        ConfigField::ReproductionCooldown,
+        ConfigField::MutationChance,
+        ConfigField::MutationPercentChange,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            ConfigField::FoodDensity => "food_density",
+            ConfigField::ReproductionCooldown => "reproduction_cooldown",
+            ConfigField::MutationChance => "mutation_chance",
+            ConfigField::MutationPercentChange => "mutation_percent_change",
+        }
+    }
+
+    pub fn get(self, config: &SimulatorConfig) -> f64 {
+        match self {
+            ConfigField::FoodDensity => config.food_density as f64,
+            ConfigField::ReproductionCooldown => config.reproduction_cooldown as f64,
+            ConfigField::MutationChance => config.mutation_chance,
+            ConfigField::MutationPercentChange => config.mutation_percent_change,
+        }
+    }
+
+    // the smallest and largest value the field can take
+    pub fn range(self) -> (f64, f64) {
+        match self {
+            // no food would spawn at 0
+            ConfigField::FoodDensity => (1.0, u32::MAX as f64),
+            ConfigField::ReproductionCooldown => (0.0, u32::MAX as f64),
+            ConfigField::MutationChance => (0.0, 1.0),
+            ConfigField::MutationPercentChange => (0.0, f64::INFINITY),
+        }
+    }
+
+    pub fn check(self, value: f64) -> Result<(), String> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{}` has to be between {} and {}, not {}",
+                self.id(),
+                min,
+                max,
+                value
+            ))
+        }
+    }
+
+    // whole number fields are rounded, negative values become 0
+    pub fn set(self, config: &mut SimulatorConfig, value: f64) {
+        match self {
+            ConfigField::FoodDensity => config.food_density = value.round() as u32,
+            ConfigField::ReproductionCooldown => {
+                config.reproduction_cooldown = value.round() as u32
+            }
+            ConfigField::MutationChance => config.mutation_chance = value,
+            ConfigField::MutationPercentChange => config.mutation_percent_change = value,
+        }
+    }
+}
+
+impl FromStr for ConfigField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ConfigField::ALL
+            .iter()
+            .copied()
+            .find(|field| field.id() == s)
+            .ok_or_else(|| {
+                let ids: Vec<&str> = ConfigField::ALL.iter().map(|field| field.id()).collect();
+                format!(
+                    "unknown config field `{}` (expected one of {})",
+                    s,
+                    ids.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Change {
+    // jumps to `value` at `step`, after that the field can be changed by other means again
+    Set {
+        step: u32,
+        value: f64,
+    },
+    // goes from `from` at `start` to `to` at `end` in a straight line, then leaves it at `to`
+    Ramp {
+        start: u32,
+        end: u32,
+        from: f64,
+        to: f64,
+    },
+    // from `start` on, goes between `low` and `high` and back every `period` steps, starting
+    // at `low`
+    Periodic {
+        start: u32,
+        period: u32,
+        low: f64,
+        high: f64,
+        waveform: Waveform,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Waveform {
+    // smooth, like temperature over a year
+    Sine,
+    // the first half of every period at `low`, the second half at `high`, like wet and dry seasons
+    Square,
+}
+
+impl Change {
+    // the value the change sets on `step`, `None` if it doesn't change the field on that step.
+    // Finished changes leave the field alone, so it can still be changed by hand afterwards.
+    pub fn value_on(&self, step: u32) -> Option<f64> {
+        match *self {
+            Change::Set { step: at, value } => (step == at).then_some(value),
+            Change::Ramp { end, .. } if step > end => None,
+            _ => self.value_at(step),
+        }
+    }
+
+    // the value after `step` steps, `None` if the change hasn't started yet
+    pub fn value_at(&self, step: u32) -> Option<f64> {
+        match *self {
+            Change::Set { step: at, value } => (step >= at).then_some(value),
+            Change::Ramp {
+                start,
+                end,
+                from,
+                to,
+            } => {
+                if step < start {
+                    None
+                } else if step >= end {
+                    Some(to)
+                } else {
+                    let progress = (step - start) as f64 / (end - start) as f64;
+                    Some(from + (to - from) * progress)
+                }
+            }
+            Change::Periodic {
+                start,
+                period,
+                low,
+                high,
+                waveform,
+            } => {
+                if step < start {
+                    return None;
+                }
+                let phase = ((step - start) % period.max(1)) as f64 / period.max(1) as f64;
+                let level = match waveform {
+                    Waveform::Sine => 0.5 - 0.5 * (2.0 * PI * phase).cos(),
+                    Waveform::Square if phase < 0.5 => 0.0,
+                    Waveform::Square => 1.0,
+                };
+                Some(low + (high - low) * level)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledChange {
+    pub field: ConfigField,
+    pub change: Change,
+}
+
+// Changes to the environment over the course of a run. The simulator applies it itself as steps
+// advance, so the config always holds the values the next step will use. When several changes to
+// the same field happen on a step, the one added last wins.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timeline {
+    changes: Vec<ScheduledChange>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `values` each get an equally long share of `steps`, e.g. `[240, 600]` over 1,000 steps
+    // switches from 240 to 600 at step 500
+    pub fn phases(field: ConfigField, values: &[f64], steps: u32) -> Self {
+        let mut timeline = Self::new();
+        for (phase, &value) in values.iter().enumerate() {
+            let step = (phase as u64 * steps as u64).div_ceil(values.len() as u64);
+            timeline.push(
+                field,
+                Change::Set {
+                    step: step as u32,
+                    value,
+                },
+            );
+        }
+        timeline
+    }
+
+    pub fn push(&mut self, field: ConfigField, change: Change) -> &mut Self {
+        self.changes.push(ScheduledChange { field, change });
+        self
+    }
+
+    pub fn changes(&self) -> &[ScheduledChange] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // the changes happening on `step`
+    pub(crate) fn apply(&self, step: u32, config: &mut SimulatorConfig) {
+        for scheduled in &self.changes {
+            if let Some(value) = scheduled.change.value_on(step) {
+                scheduled.field.set(config, value);
+            }
+        }
+    }
+
+    // every change that has started by `step`, for a timeline given in the middle of a run
+    pub(crate) fn catch_up(&self, step: u32, config: &mut SimulatorConfig) {
+        for scheduled in &self.changes {
+            if let Some(value) = scheduled.change.value_at(step) {
+                scheduled.field.set(config, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt::Write;
+
+use crate::experiment::{self, Experiment, Metric};
+use crate::genes::Gene;
+use crate::progress::{CancellationToken, ProgressObserver};
+use crate::recorder::{Recorder, RowFormat, Sink};
+use crate::renderer;
+use crate::simulator::{Reproduction, Simulator, SimulatorConfig};
+use crate::timeline::{ConfigField, Timeline};
+use wasm_bindgen::prelude::*;
+use web_sys::{console, CanvasRenderingContext2d};
+
+// This is like the `main` function, except for JavaScript.
+#[wasm_bindgen(start)]
+pub fn main_js() -> Result<(), JsValue> {
+    // This provides better error messages in debug mode.
+    // It's disabled in release mode so it doesn't bloat up the file size.
+    #[cfg(debug_assertions)]
+    console_error_panic_hook::set_once();
+
+    // Your code goes here!
+    console::log_1(&JsValue::from_str("Rust module loaded"));
+
+    Ok(())
+}
+
+// Each `Simulator` is its own independent world, so a page can create as many as it wants.
+#[wasm_bindgen]
+impl Simulator {
+    #[wasm_bindgen(constructor)]
+    pub fn js_new(repro_method: &str, seed: Option<u64>) -> Simulator {
+        let mut config = SimulatorConfig::new(parse_reproductive_method(repro_method));
+        if let Some(seed) = seed {
+            config.seed = seed;
+        }
+        Simulator::with_config(config)
+    }
+
+    #[wasm_bindgen(js_name = load)]
+    pub fn js_load(data: &str) -> Result<Simulator, JsValue> {
+        Simulator::load(data).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    #[wasm_bindgen(js_name = save)]
+    pub fn js_save(&self) -> String {
+        self.save()
+    }
+
+    pub fn step(&mut self, count: u32) {
+        for _ in 0..count {
+            self.simulate_step();
+        }
+    }
+
+    pub fn render(&self, context: CanvasRenderingContext2d, camera_x: i32, camera_y: i32) {
+        renderer::render_simulator(self, &context, camera_x, camera_y);
+    }
+
+    pub fn set_food_density(&mut self, density: u32) {
+        self.get_config_mut().food_density = density;
+
+        console::log_2(
+            &JsValue::from_str("Set food density to %d"),
+            &density.into(),
+        );
+    }
+
+    pub fn set_reproductive_method(&mut self, repro_method: &str) {
+        self.get_config_mut().reproduction = parse_reproductive_method(repro_method);
+
+        console::log_2(
+            &JsValue::from_str("Set reproductive method to %s"),
+            &repro_method.into(),
+        );
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        // restart the simulation from scratch so the whole run can be reproduced from the seed,
+        // the scheduled changes start over with it
+        let mut config = *self.get_config();
+        config.seed = seed;
+        let timeline = self.get_timeline().clone();
+        *self = Simulator::with_config(config);
+        self.set_timeline(timeline);
+
+        console::log_2(
+            &JsValue::from_str("Set seed to %s"),
+            &seed.to_string().into(),
+        );
+    }
+
+    // `timeline` is the JSON form of a `Timeline`
+    #[wasm_bindgen(js_name = set_timeline)]
+    pub fn js_set_timeline(&mut self, timeline: &str) -> Result<(), JsValue> {
+        let timeline: Timeline =
+            serde_json::from_str(timeline).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.set_timeline(timeline);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> u32 {
+        self.get_steps()
+    }
+
+    // `undefined` while any cell is alive
+    #[wasm_bindgen(getter)]
+    pub fn extinction_step(&self) -> Option<u32> {
+        self.get_extinction_step()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cell_count(&self) -> usize {
+        self.get_cells().len()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn food_density(&self) -> u32 {
+        self.get_config().food_density
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u64 {
+        self.get_config().seed
+    }
+
+    #[wasm_bindgen(js_name = get_phylogeny_newick)]
+    pub fn js_get_phylogeny_newick(&self) -> String {
+        self.get_phylogeny_newick()
+    }
+
+    // the whole world as JSON, see `Snapshot`
+    #[wasm_bindgen(js_name = get_snapshot_json)]
+    pub fn js_get_snapshot_json(&self) -> String {
+        self.snapshot_json()
+    }
+
+    // `{"gene": "size", "edges": [...], "counts": [...], "below": 0, "above": 0}`, see `Histogram`
+    #[wasm_bindgen(js_name = get_gene_histogram_json)]
+    pub fn js_get_gene_histogram_json(
+        &self,
+        gene: &str,
+        min: f64,
+        max: f64,
+        bins: usize,
+    ) -> Result<String, JsValue> {
+        let gene: Gene = gene
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        if bins == 0 || max <= min {
+            return Err(JsValue::from_str(
+                "histograms need bins and a max above their min",
+            ));
+        }
+        let histogram = self.gene_histogram(gene, min, max, bins);
+        Ok(serde_json::to_string(&histogram).expect("histograms should always be serializable"))
+    }
+
+    // e.g. `[0.25, 0.5, 0.75]` for the quartiles
+    #[wasm_bindgen(js_name = get_gene_quantiles)]
+    pub fn js_get_gene_quantiles(
+        &self,
+        gene: &str,
+        quantiles: Vec<f64>,
+    ) -> Result<Vec<f64>, JsValue> {
+        let gene: Gene = gene
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        if quantiles
+            .iter()
+            .any(|quantile| !(0.0..=1.0).contains(quantile))
+        {
+            return Err(JsValue::from_str("quantiles have to be between 0 and 1"));
+        }
+        Ok(self.gene_quantiles(gene, &quantiles))
+    }
+
+    pub fn get_cells_data_csv(&self) -> String {
+        let mut result = format!("Step #{}\nCell #,x,y", self.get_steps());
+        let genes: Vec<Gene> = Gene::enabled(self.get_config()).collect();
+        for gene in &genes {
+            let _ = write!(result, ",{}", gene.definition().cells_column);
+        }
+
+        let cells = self.get_cells();
+        let mut averages = vec![0.0; genes.len()];
+        for cell in cells {
+            // writing to a `String` can't fail
+            let _ = write!(result, "\n{},{},{}", cell.id(), cell.x, cell.y);
+            for (&gene, average) in genes.iter().zip(averages.iter_mut()) {
+                let value = cell.genes[gene];
+                *average += value;
+                let _ = write!(result, ",{}", value);
+            }
+        }
+
+        result += "\nAVERAGE,,";
+        for average in averages {
+            let _ = write!(result, ",{}", average / cells.len() as f64);
+        }
+
+        result
+    }
+}
+
+fn parse_reproductive_method(repro_method: &str) -> Reproduction {
+    repro_method.parse().unwrap_or(Reproduction::Sexual)
+}
+
+#[wasm_bindgen]
+impl CancellationToken {
+    #[wasm_bindgen(constructor)]
+    pub fn js_new() -> CancellationToken {
+        CancellationToken::new()
+    }
+
+    #[wasm_bindgen(js_name = cancel)]
+    pub fn js_cancel(&self) {
+        self.cancel();
+    }
+
+    #[wasm_bindgen(getter = cancelled)]
+    pub fn js_is_cancelled(&self) -> bool {
+        self.is_cancelled()
+    }
+}
+
+// Calls a JS function with every row
+struct JsSink(js_sys::Function);
+
+impl Sink for JsSink {
+    fn write_row(&mut self, row: &str) -> std::io::Result<()> {
+        self.0
+            .call1(&JsValue::NULL, &JsValue::from_str(row))
+            .map(|_| ())
+            .map_err(|err| std::io::Error::other(format!("{:?}", err)))
+    }
+}
+
+// A `Recorder` that hands its CSV rows to a JS function
+#[wasm_bindgen(js_name = Recorder)]
+pub struct JsRecorder {
+    recorder: Recorder<JsSink>,
+}
+
+#[wasm_bindgen(js_class = Recorder)]
+impl JsRecorder {
+    // `metrics` is a comma separated list of metric ids, e.g. "population,size", leave it out
+    // to record population, % food available and the means of the genes `simulator` has
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        interval: u32,
+        on_row: js_sys::Function,
+        simulator: &Simulator,
+        metrics: Option<String>,
+    ) -> Result<JsRecorder, JsValue> {
+        let sink = JsSink(on_row);
+        let recorder = match metrics {
+            None => Recorder::with_builtin_metrics(interval, sink, simulator.get_config()),
+            Some(metrics) => {
+                let mut recorder = Recorder::new(interval, sink);
+                for metric in metrics.split(',') {
+                    let metric: Metric = metric
+                        .trim()
+                        .parse()
+                        .map_err(|err: String| JsValue::from_str(&err))?;
+                    recorder.add_builtin(metric);
+                }
+                recorder
+            }
+        };
+        Ok(JsRecorder { recorder })
+    }
+
+    // "csv" or "ndjson"
+    pub fn set_format(&mut self, format: &str) -> Result<(), JsValue> {
+        let format = match format {
+            "csv" => RowFormat::Csv,
+            "ndjson" => RowFormat::Ndjson,
+            _ => return Err(JsValue::from_str(&format!("unknown format `{}`", format))),
+        };
+        self.recorder.set_format(format);
+        Ok(())
+    }
+
+    // call after stepping, writes a row whenever the simulator is at a multiple of `interval`
+    pub fn observe(&mut self, simulator: &Simulator) -> Result<(), JsValue> {
+        self.recorder
+            .observe(simulator)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    pub fn record(&mut self, simulator: &Simulator) -> Result<(), JsValue> {
+        self.recorder
+            .record(simulator)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+}
+
+// Calls a JS function with the completion percentage
+struct JsProgressObserver<'a>(&'a js_sys::Function);
+
+impl ProgressObserver for JsProgressObserver<'_> {
+    fn on_progress(&mut self, percent: u32) {
+        if let Err(err) = self.0.call1(&JsValue::NULL, &percent.into()) {
+            console::error_1(&err);
+        }
+    }
+}
+
+// `on_progress` is called with the completion percentage. Cancelling `cancel` (e.g. from inside
+// `on_progress`) stops the run, which then throws an error instead of returning the CSV.
+#[wasm_bindgen]
+pub fn get_results_csv(
+    repro_method: &str,
+    beginning_food_density: u32,
+    switched_food_density: u32,
+    on_progress: &js_sys::Function,
+    cancel: &CancellationToken,
+) -> Result<String, JsValue> {
+    let config = SimulatorConfig::new(parse_reproductive_method(repro_method));
+    let mut experiment = Experiment::new(config);
+    experiment.timeline = Timeline::phases(
+        ConfigField::FoodDensity,
+        &[beginning_food_density as f64, switched_food_density as f64],
+        experiment.steps,
+    );
+
+    experiment::get_results_csv(&experiment, JsProgressObserver(on_progress), cancel)
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
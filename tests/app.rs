@@ -1,26 +1,1620 @@
-use wasm_bindgen_test::{wasm_bindgen_test_configure, wasm_bindgen_test};
+use choanosim::cell::{Cell, CellId};
+use choanosim::diploid::{Genotype, Ploidy};
+use choanosim::distribution;
+use choanosim::experiment::{self, Experiment, Metric, SampleColumns, Summary};
+use choanosim::genes::{Gene, Genes, MutationKernel};
+use choanosim::mating::{MatingTypes, FEMALE, MALE};
+use choanosim::model::{ModelParameter, ModelParameters};
+use choanosim::progress::{CancellationToken, Cancelled};
+use choanosim::randoms::Random;
+use choanosim::recorder::{Recorder, RowFormat, WriteSink};
+use choanosim::save::{LoadError, SAVE_FORMAT_VERSION};
+use choanosim::sensitivity::Morris;
+use choanosim::simulator::{Extinction, Reproduction, Simulator, SimulatorConfig};
+use choanosim::spatial::SpatialGrid;
+use choanosim::sweep::Sweep;
+use choanosim::timeline::{Change, ConfigField, Timeline, Waveform};
+
+#[cfg(target_arch = "wasm32")]
 use futures::prelude::*;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsValue;
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen_futures::JsFuture;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
 
+#[cfg(target_arch = "wasm32")]
 wasm_bindgen_test_configure!(run_in_browser);
 
+fn seeded_simulator(repro_method: Reproduction, seed: u64) -> Simulator {
+    let mut config = SimulatorConfig::new(repro_method);
+    config.seed = seed;
+    Simulator::with_config(config)
+}
+
+// a tiny world where food doesn't grow back, every cell starves within a few hundred steps
+fn scarce_config(seed: u64) -> SimulatorConfig {
+    let mut config = SimulatorConfig::new(Reproduction::Asexual);
+    config.seed = seed;
+    config.width = 400;
+    config.height = 400;
+    config.cell_number = 10;
+    config.food_density = 1_000_000;
+    config
+}
+
+fn seeded_experiment(repro_method: Reproduction, seed: u64) -> Experiment {
+    let mut config = SimulatorConfig::new(repro_method);
+    config.seed = seed;
+    Experiment::new(config)
+}
+
+// Seeded reference runs whose trajectories are checked in under `tests/golden`, so any change
+// to how cells, genes or the simulator behave fails with a diff of what moved. After an
+// intended change, regenerate them with `UPDATE_GOLDEN=1 cargo test golden` and commit the new
+// files along with it.
+struct GoldenRun {
+    name: &'static str,
+    expected: &'static str,
+    simulator: Simulator,
+    steps: u32,
+    interval: u32,
+}
+
+fn golden_runs() -> Vec<GoldenRun> {
+    let mut sexual = SimulatorConfig::new(Reproduction::Sexual);
+    sexual.seed = 2102;
+    sexual.mutation_chance = 0.2;
+
+    let mut changing = SimulatorConfig::new(Reproduction::Sexual);
+    changing.seed = 2103;
+    changing.model.base_speed = 6.0;
+    let mut timeline = Timeline::new();
+    timeline
+        .push(
+            ConfigField::FoodDensity,
+            Change::Ramp {
+                start: 0,
+                end: 2_000,
+                from: 240.0,
+                to: 600.0,
+            },
+        )
+        .push(
+            ConfigField::MutationChance,
+            Change::Periodic {
+                start: 0,
+                period: 500,
+                low: 0.01,
+                high: 0.3,
+                waveform: Waveform::Square,
+            },
+        );
+    let mut changing = Simulator::with_config(changing);
+    changing.set_timeline(timeline);
+
+    vec![
+        GoldenRun {
+            name: "asexual",
+            expected: include_str!("golden/asexual.csv"),
+            simulator: seeded_simulator(Reproduction::Asexual, 2101),
+            steps: 2_000,
+            interval: 200,
+        },
+        GoldenRun {
+            name: "sexual",
+            expected: include_str!("golden/sexual.csv"),
+            simulator: Simulator::with_config(sexual),
+            steps: 2_000,
+            interval: 200,
+        },
+        GoldenRun {
+            name: "changing_environment",
+            expected: include_str!("golden/changing_environment.csv"),
+            simulator: changing,
+            steps: 2_000,
+            interval: 200,
+        },
+        GoldenRun {
+            name: "extinction",
+            expected: include_str!("golden/extinction.csv"),
+            simulator: Simulator::with_config(scarce_config(2104)),
+            steps: 400,
+            interval: 25,
+        },
+    ]
+}
+
+impl GoldenRun {
+    // the summary of the run that gets checked in
+    fn trajectory(mut self) -> String {
+        let mut recorder = Recorder::with_builtin_metrics(
+            self.interval,
+            String::new(),
+            self.simulator.get_config(),
+        );
+        recorder
+            .add_builtin(Metric::TimeToExtinction)
+            .add_metric("Genealogy Records", |simulator| {
+                simulator.get_genealogy().len() as f64
+            });
+        for metric in Metric::quantiles(Gene::SIZE, &[0.1, 0.5, 0.9]) {
+            recorder.add_builtin(metric);
+        }
+        recorder.record(&self.simulator).unwrap();
+        recorder.run(&mut self.simulator, self.steps).unwrap();
+        recorder.into_sink()
+    }
+
+    fn check(self) {
+        let (name, expected) = (self.name, self.expected);
+        let actual = self.trajectory();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let path = format!("{}/tests/golden/{}.csv", env!("CARGO_MANIFEST_DIR"), name);
+            std::fs::write(path, &actual).unwrap();
+            return;
+        }
+
+        let differences = golden_diff(expected, &actual);
+        assert!(
+            differences.is_empty(),
+            "the `{}` golden run changed, if that's intended run `UPDATE_GOLDEN=1 cargo test \
+             golden` and commit tests/golden/{}.csv\n{}",
+            name,
+            name,
+            differences.join("\n")
+        );
+    }
+}
+
+// one line per value that moved, like `  step 600, Population Size: 53 -> 55`
+fn golden_diff(expected: &str, actual: &str) -> Vec<String> {
+    const MAX_LINES: usize = 30;
+    let rows = |csv: &str| -> Vec<Vec<String>> {
+        csv.lines()
+            .map(|row| row.split(',').map(str::to_string).collect())
+            .collect()
+    };
+    let (expected, actual) = (rows(expected), rows(actual));
+    // columns are matched by name, moving one around doesn't change what was measured
+    let header = &expected[0];
+    let actual_header = actual.first().cloned().unwrap_or_default();
+    let columns: Vec<Option<usize>> = header
+        .iter()
+        .map(|name| actual_header.iter().position(|other| other == name))
+        .collect();
+    let mut differences: Vec<String> = header
+        .iter()
+        .zip(&columns)
+        .filter(|(_, column)| column.is_none())
+        .map(|(name, _)| format!("  column {}: missing", name))
+        .collect();
+    differences.extend(
+        actual_header
+            .iter()
+            .filter(|name| !header.contains(name))
+            .map(|name| format!("  column {}: added", name)),
+    );
+
+    // values are compared with a little slack, so last-digit differences in the math library
+    // of another platform don't count
+    let same = |a: &str, b: &str| match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => {
+            (a.is_nan() && b.is_nan()) || (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+        }
+        _ => a == b,
+    };
+
+    for index in 1..expected.len().max(actual.len()) {
+        match (expected.get(index), actual.get(index)) {
+            (Some(expected), Some(actual)) => {
+                for (index, name) in header.iter().enumerate().skip(1) {
+                    let Some(column) = columns[index] else {
+                        continue;
+                    };
+                    if !same(&expected[index], &actual[column]) {
+                        differences.push(format!(
+                            "  step {}, {}: {} -> {}",
+                            expected[0], name, expected[index], actual[column]
+                        ));
+                    }
+                }
+            }
+            (Some(expected), None) => differences.push(format!("  step {}: missing", expected[0])),
+            (None, Some(actual)) => differences.push(format!("  step {}: added", actual[0])),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    if differences.len() > MAX_LINES {
+        let more = differences.len() - MAX_LINES;
+        differences.truncate(MAX_LINES);
+        differences.push(format!("  ... and {} more", more));
+    }
+    differences
+}
+
+#[test]
+fn golden_runs_match_their_checked_in_trajectories() {
+    for run in golden_runs() {
+        run.check();
+    }
+}
 
-// This runs a unit test in native Rust, so it can only use Rust APIs.
 #[test]
-fn rust_test() {
-    assert_eq!(1, 1);
+fn golden_diffs_point_at_what_moved() {
+    let expected = "Step #,Population Size,Avg. Size\n0,50,30\n200,61,30.5";
+    assert!(golden_diff(expected, expected).is_empty());
+    assert_eq!(
+        golden_diff(
+            expected,
+            "Step #,Population Size,Avg. Size\n0,50,30\n200,64,30.5"
+        ),
+        vec!["  step 200, Population Size: 61 -> 64"]
+    );
+    assert!(golden_diff(
+        expected,
+        "Step #,Avg. Size,Population Size\n0,30,50\n200,30.5,61"
+    )
+    .is_empty());
+    assert_eq!(
+        golden_diff(expected, "Step #,Population Size,Avg. Size\n0,50,30"),
+        vec!["  step 200: missing"]
+    );
 }
 
+#[test]
+fn same_seed_gives_same_trajectory() {
+    for repro_method in [Reproduction::Asexual, Reproduction::Sexual] {
+        let mut a = seeded_simulator(repro_method, 23575);
+        let mut b = seeded_simulator(repro_method, 23575);
+        for _ in 0..2_000 {
+            a.simulate_step();
+            b.simulate_step();
+        }
 
-// This runs a unit test in the browser, so it can use browser APIs.
-#[wasm_bindgen_test]
-fn web_test() {
-    assert_eq!(1, 1);
+        assert_eq!(a.get_steps(), b.get_steps());
+        assert_eq!(a.get_cells().len(), b.get_cells().len());
+        for (cell_a, cell_b) in a.get_cells().iter().zip(b.get_cells()) {
+            assert_eq!((cell_a.x, cell_a.y), (cell_b.x, cell_b.y));
+            assert_eq!(cell_a.genes[Gene::SIZE], cell_b.genes[Gene::SIZE]);
+            assert_eq!(
+                cell_a.genes[Gene::FLAGELLUM_SIZE],
+                cell_b.genes[Gene::FLAGELLUM_SIZE]
+            );
+        }
+    }
+}
+
+#[test]
+fn different_seeds_diverge() {
+    let mut a = seeded_simulator(Reproduction::Sexual, 1);
+    let mut b = seeded_simulator(Reproduction::Sexual, 2);
+    for _ in 0..100 {
+        a.simulate_step();
+        b.simulate_step();
+    }
+
+    let positions = |sim: &Simulator| -> Vec<(u32, u32)> {
+        sim.get_cells()
+            .iter()
+            .map(|cell| (cell.x, cell.y))
+            .collect()
+    };
+    assert_ne!(positions(&a), positions(&b));
+}
+
+#[test]
+fn loaded_simulator_continues_identically() {
+    let mut original = seeded_simulator(Reproduction::Sexual, 11);
+    for _ in 0..500 {
+        original.simulate_step();
+    }
+
+    let mut loaded = Simulator::load(&original.save()).expect("save should load");
+    assert_eq!(loaded.save(), original.save());
+
+    for _ in 0..500 {
+        original.simulate_step();
+        loaded.simulate_step();
+    }
+    assert_eq!(loaded.get_steps(), 1_000);
+    assert_eq!(loaded.save(), original.save());
+}
+
+#[test]
+fn loading_other_save_versions_fails() {
+    let save = seeded_simulator(Reproduction::Asexual, 3).save();
+    let future_save = save.replacen(
+        &format!("\"version\":{}", SAVE_FORMAT_VERSION),
+        &format!("\"version\":{}", SAVE_FORMAT_VERSION + 1),
+        1,
+    );
+
+    assert!(matches!(
+        Simulator::load(&future_save),
+        Err(LoadError::UnsupportedVersion(version)) if version == SAVE_FORMAT_VERSION + 1
+    ));
+    assert!(matches!(
+        Simulator::load("not a save"),
+        Err(LoadError::Invalid(_))
+    ));
+}
+
+#[test]
+fn spatial_queries_match_brute_force() {
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 5);
+    for _ in 0..1_500 {
+        simulator.simulate_step();
+    }
+
+    let dist = |id: CellId, x: u32, y: u32| {
+        let cell = simulator.get_cell(id).expect("cell should exist");
+        ((cell.x.abs_diff(x) as f64).powi(2) + (cell.y.abs_diff(y) as f64).powi(2)).sqrt()
+    };
+    let living: Vec<CellId> = simulator
+        .get_cells()
+        .iter()
+        .filter(|cell| cell.alive)
+        .map(|cell| cell.id())
+        .collect();
+    for &(x, y, radius) in &[(2_500, 2_500, 300.0), (40, 40, 120.0), (4_900, 100, 75.0)] {
+        let mut found = simulator.cells_within(x, y, radius);
+        found.sort_unstable();
+        let expected: Vec<CellId> = living
+            .iter()
+            .copied()
+            .filter(|&id| dist(id, x, y) <= radius)
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    for cell in simulator.get_cells().iter().take(20) {
+        let nearest = simulator.nearest_cell(cell.x, cell.y, Some(cell.id()));
+        let closest = living
+            .iter()
+            .filter(|&&other| other != cell.id())
+            .map(|&other| dist(other, cell.x, cell.y))
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(
+            nearest.map(|other| dist(other, cell.x, cell.y)),
+            Some(closest)
+        );
+    }
+}
+
+#[test]
+fn cell_ids_are_unique_and_stable() {
+    let mut simulator = seeded_simulator(Reproduction::Asexual, 9);
+    let first_ids: Vec<CellId> = simulator.get_cells().iter().map(|cell| cell.id()).collect();
+    assert_eq!(first_ids, (0..50).map(CellId).collect::<Vec<_>>());
+
+    let followed = CellId(17);
+    let start_genes = simulator.get_cell(followed).unwrap().genes[Gene::SIZE];
+    let mut seen_deaths = false;
+    for _ in 0..3_000 {
+        simulator.simulate_step();
+
+        let ids: Vec<CellId> = simulator.get_cells().iter().map(|cell| cell.id()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        for (index, &id) in ids.iter().enumerate() {
+            assert_eq!(simulator.get_cell_index(id), Some(index));
+        }
+
+        match simulator.get_cell(followed) {
+            Some(cell) => assert_eq!(cell.genes[Gene::SIZE], start_genes),
+            None => seen_deaths = true,
+        }
+    }
+    assert!(seen_deaths, "cell {} should have died by now", followed);
+}
+
+#[test]
+fn genealogy_keeps_only_ancestors_of_the_living() {
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 21);
+    for step in 1..=4_000 {
+        simulator.simulate_step();
+        // dead cells are only kept where the tree branches or for a child on the way
+        if step % 500 == 0 {
+            let cells = simulator.get_cells();
+            let carrying = cells
+                .iter()
+                .filter(|cell| cell.child_father().is_some())
+                .count();
+            assert!(simulator.get_genealogy().len() <= 2 * (cells.len() + carrying));
+        }
+    }
+
+    let genealogy = simulator.get_genealogy();
+    for cell in simulator.get_cells().iter().filter(|cell| cell.alive) {
+        let record = genealogy.get(cell.id()).expect("living cells have records");
+        assert_eq!(record.death_step, None);
+    }
+
+    let mut births = 0;
+    for record in genealogy.records() {
+        // pruning never leaves a dangling mother, fathers are only kept while they're needed
+        if let Some(mother) = record.mother {
+            let mother = genealogy
+                .get(mother)
+                .expect("mothers of kept cells are kept");
+            assert!(mother.children().contains(&record.id));
+            assert!(mother.birth_step <= record.split_step);
+        }
+        if let Some(father) = record.father.and_then(|father| genealogy.get(father)) {
+            assert!(father.children().contains(&record.id));
+        }
+        assert!(record.split_step <= record.birth_step);
+        if record.mother.is_some() {
+            births += 1;
+            assert!(record.father.is_some(), "sexual children have two parents");
+        }
+        if let Some(death_step) = record.death_step {
+            assert!(death_step >= record.birth_step);
+            assert!(death_step <= simulator.get_steps());
+        }
+    }
+    assert!(births > 0);
+
+    // extinct lineages were pruned
+    let born = simulator.get_cells().last().unwrap().id().0 + 1;
+    assert!((genealogy.len() as u64) < born);
+}
+
+// (label, branch length, children) parsed from Newick, ignoring annotations
+struct NewickNode {
+    label: String,
+    length: u32,
+    children: Vec<NewickNode>,
+}
+
+fn parse_newick(newick: &str) -> NewickNode {
+    fn parse_node(chars: &mut std::iter::Peekable<std::str::Chars>) -> NewickNode {
+        let mut children = Vec::new();
+        if chars.peek() == Some(&'(') {
+            chars.next();
+            loop {
+                children.push(parse_node(chars));
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(')') => break,
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        }
+        let mut label = String::new();
+        let mut length = String::new();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '[' => while chars.next() != Some(']') {},
+                ':' => {
+                    chars.next();
+                    while let Some(&digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                        length.push(digit);
+                        chars.next();
+                    }
+                }
+                ',' | ')' | ';' => break,
+                _ => {
+                    label.push(c);
+                    chars.next();
+                }
+            }
+        }
+        NewickNode {
+            label,
+            length: length.parse().unwrap_or(0),
+            children,
+        }
+    }
+
+    assert!(newick.ends_with(';'));
+    parse_node(&mut newick.chars().peekable())
+}
+
+#[test]
+fn phylogeny_contains_every_living_cell_once() {
+    for repro_method in [Reproduction::Asexual, Reproduction::Sexual] {
+        let mut simulator = seeded_simulator(repro_method, 4);
+        for _ in 0..3_000 {
+            simulator.simulate_step();
+        }
+
+        let newick = simulator.get_phylogeny_newick();
+        // every tip is `cellN:length[&&NHX:...]`, the annotation comes after the branch length
+        let pieces: Vec<&str> = newick.split("[&&NHX:").collect();
+        assert_eq!(
+            pieces.len() - 1,
+            simulator
+                .get_cells()
+                .iter()
+                .filter(|cell| cell.alive)
+                .count()
+        );
+        for before in &pieces[..pieces.len() - 1] {
+            let tip = before.rsplit(['(', ',', ']']).next().unwrap();
+            let (label, length) = tip.split_once(':').unwrap();
+            assert!(
+                label.strip_prefix("cell").unwrap().parse::<u64>().is_ok(),
+                "{}",
+                tip
+            );
+            assert!(length.parse::<u32>().is_ok(), "{}", tip);
+        }
+
+        let tree = parse_newick(&newick);
+        // (tip label, distance from the root)
+        let mut tips = Vec::new();
+        let mut stack = vec![(&tree, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let depth = depth + node.length;
+            if node.children.is_empty() {
+                tips.push((node.label.clone(), depth));
+            }
+            stack.extend(node.children.iter().map(|child| (child, depth)));
+        }
+
+        let mut living: Vec<String> = simulator
+            .get_cells()
+            .iter()
+            .filter(|cell| cell.alive)
+            .map(|cell| format!("cell{}", cell.id()))
+            .collect();
+        let mut tip_labels: Vec<String> = tips.iter().map(|(label, _)| label.clone()).collect();
+        living.sort();
+        tip_labels.sort();
+        assert_eq!(tip_labels, living);
+
+        // all founders start at step 0 and every tip is at the current step
+        for (label, depth) in tips {
+            assert_eq!(
+                depth,
+                simulator.get_steps(),
+                "{} is at the wrong depth",
+                label
+            );
+        }
+    }
+}
+
+#[test]
+fn extinction_happens_once_and_freezes_the_world() {
+    let mut simulator = Simulator::with_config(scarce_config(1));
+    let mut extinctions = Vec::new();
+    for _ in 0..1_000 {
+        if let Some(extinction) = simulator.simulate_step() {
+            extinctions.push(extinction);
+        }
+    }
+
+    assert_eq!(extinctions.len(), 1);
+    let Extinction { step } = extinctions[0];
+    assert_eq!(simulator.get_extinction_step(), Some(step));
+    assert!(simulator.is_extinct());
+    assert_eq!(simulator.get_steps(), 1_000);
+    assert!(simulator.get_cells().is_empty());
+    assert!(simulator.get_genealogy().is_empty());
+
+    let loaded = Simulator::load(&simulator.save()).unwrap();
+    assert_eq!(loaded.get_extinction_step(), Some(step));
+}
+
+#[test]
+fn extinct_replicates_report_time_to_extinction() {
+    let mut experiment = Experiment::new(scarce_config(30));
+    experiment.replicates = 3;
+    experiment.steps = 1_000;
+    experiment.sample_interval = 500;
+    let results =
+        experiment::run_experiment(&experiment, |_| {}, &CancellationToken::new()).unwrap();
+
+    let extinction_steps: Vec<f64> = (0..3)
+        .map(|replicate| {
+            let mut simulator = Simulator::with_config(scarce_config(30 + replicate));
+            while simulator.simulate_step().is_none() {}
+            simulator.get_steps() as f64
+        })
+        .collect();
+
+    let metric = |metric| {
+        results
+            .metrics
+            .iter()
+            .position(|&other| other == metric)
+            .unwrap()
+    };
+    let last = results.samples.last().unwrap();
+    let time_to_extinction = last.summary(metric(Metric::TimeToExtinction));
+    assert_eq!(time_to_extinction.count, 3);
+    assert_eq!(time_to_extinction, Summary::of(extinction_steps.clone()));
+    assert_eq!(last.mean(metric(Metric::Surviving)), 0.0);
+    // halfway through, the replicates still alive are left out of the time to extinction
+    let halfway = &results.samples[0];
+    let alive = extinction_steps
+        .iter()
+        .filter(|&&step| step > 500.0)
+        .count();
+    assert_eq!(
+        halfway.mean(metric(Metric::Surviving)),
+        alive as f64 / 3.0 * 100.0
+    );
+    assert_eq!(
+        halfway.summary(metric(Metric::TimeToExtinction)).count,
+        3 - alive
+    );
+    assert_eq!(last.mean(metric(Metric::PopulationSize)), 0.0);
+    assert_eq!(last.summary(metric(Metric::GeneMean(Gene::SIZE))).count, 0);
+    assert_eq!(last.summary(metric(Metric::FoodAvailable)).count, 0);
+}
+
+#[test]
+fn recorder_streams_builtin_and_custom_metrics() {
+    let config = SimulatorConfig::new(Reproduction::Sexual);
+    let mut recorder = Recorder::with_builtin_metrics(250, String::new(), &config);
+    recorder.add_metric("Living Cells", |simulator| {
+        simulator
+            .get_cells()
+            .iter()
+            .filter(|cell| cell.alive)
+            .count() as f64
+    });
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 17);
+    recorder.run(&mut simulator, 1_000).unwrap();
+
+    let csv = recorder.into_sink();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        rows[0],
+        "Step #,Population Size,% Food Available,Avg. Size,Avg. Flagellum Size,Avg. Stomach Size,Avg. Gestation Steps,Living Cells"
+    );
+    assert_eq!(rows.len(), 1 + 4);
+    let last: Vec<&str> = rows[4].split(',').collect();
+    assert_eq!(last[0], "1000");
+    assert_eq!(last[1], simulator.get_cells().len().to_string());
+    assert_eq!(
+        last[2],
+        Metric::FoodAvailable.measure(&simulator).to_string()
+    );
+
+    // the same rows stream into any writer
+    let mut recorder = Recorder::with_builtin_metrics(250, WriteSink(Vec::new()), &config);
+    recorder.add_metric("Living Cells", |simulator| {
+        simulator
+            .get_cells()
+            .iter()
+            .filter(|cell| cell.alive)
+            .count() as f64
+    });
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 17);
+    recorder.run(&mut simulator, 1_000).unwrap();
+    recorder.flush().unwrap();
+    assert_eq!(String::from_utf8(recorder.into_sink().0).unwrap(), csv);
+}
+
+#[test]
+fn gene_registry_drives_genes_and_exports() {
+    let ids: Vec<&str> = Gene::all().map(Gene::id).collect();
+    assert_eq!(
+        ids,
+        [
+            "size",
+            "stomach_size",
+            "flagellum_size",
+            "gestation_steps",
+            "mutation_rate"
+        ]
+    );
+    // exports keep the columns of the original "Get Results" CSV
+    let config = SimulatorConfig::new(Reproduction::Asexual);
+    let names: Vec<String> = Metric::all(&config)
+        .iter()
+        .take(6)
+        .map(|m| m.name())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "Population Size",
+            "% Food Available",
+            "Avg. Size",
+            "Avg. Flagellum Size",
+            "Avg. Stomach Size",
+            "Avg. Gestation Steps"
+        ]
+    );
+    assert_eq!("stomach_size".parse(), Ok(Gene::STOMACH_SIZE));
+    assert!("speed".parse::<Gene>().is_err());
+
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 22);
+    for _ in 0..500 {
+        simulator.simulate_step();
+    }
+    for cell in simulator.get_cells() {
+        assert!(cell.genes[Gene::STOMACH_SIZE] <= cell.genes[Gene::SIZE]);
+    }
+
+    // genes are saved by id and load back exactly
+    let genes = simulator.get_cells()[0].genes;
+    let json = serde_json::to_value(genes).unwrap();
+    assert_eq!(json.as_object().unwrap().len(), Gene::all().count());
+    assert_eq!(json["gestation_steps"], genes[Gene::GESTATION_STEPS]);
+    assert_eq!(serde_json::from_value::<Genes>(json).unwrap(), genes);
+    assert!(serde_json::from_str::<Genes>(r#"{"size": 30}"#).is_err());
+
+    // genes the config doesn't have aren't exported
+    let config = *simulator.get_config();
+    assert!(!Gene::MUTATION_RATE.is_enabled(&config));
+    let metrics: Vec<String> = Metric::all(&config).iter().map(|m| m.name()).collect();
+    let newick = simulator.get_phylogeny_newick();
+    let snapshot: serde_json::Value = serde_json::from_str(&simulator.snapshot_json()).unwrap();
+    for gene in Gene::all() {
+        let exported = gene.is_enabled(&config);
+        assert_eq!(metrics.contains(&format!("Avg. {}", gene.name())), exported);
+        assert_eq!(newick.contains(&format!(":{}=", gene.id())), exported);
+        assert_eq!(
+            snapshot["cells"][0]["genes"].get(gene.id()).is_some(),
+            exported
+        );
+    }
+    let mut evolvable = config;
+    evolvable.evolvable_mutation_rate = true;
+    assert!(Metric::all(&evolvable).contains(&Metric::GeneMean(Gene::MUTATION_RATE)));
+}
+
+#[test]
+fn mutation_kernels_rates_and_evolvable_rates() {
+    let mut config = SimulatorConfig::new(Reproduction::Asexual);
+    config.mutation_chance = 1.0;
+    let mut rng = Random::new(23);
+    let parent = Genes::new(&config, &mut rng);
+    let sizes = |config: &SimulatorConfig, rng: &mut Random| -> Vec<f64> {
+        (0..2_000)
+            .map(|_| parent.mix(&parent, config, rng)[Gene::SIZE])
+            .collect()
+    };
+
+    // exactly 10% of 30 up or down
+    assert!(sizes(&config, &mut rng)
+        .iter()
+        .all(|&size| (size - 27.0).abs() < 1e-9 || (size - 33.0).abs() < 1e-9));
+
+    config.mutation_kernel = MutationKernel::Gaussian;
+    let summary = Summary::of(sizes(&config, &mut rng));
+    assert!((summary.mean - 30.0).abs() < 0.3);
+    assert!((summary.sd - 3.0).abs() < 0.3);
+
+    config.mutation_kernel = MutationKernel::Uniform;
+    let uniform = sizes(&config, &mut rng);
+    assert!(uniform.iter().all(|size| (27.0..=33.0).contains(size)));
+    assert!((Summary::of(uniform).sd - 6.0 / 12f64.sqrt()).abs() < 0.2);
+
+    config.mutation_kernel = MutationKernel::LogNormal;
+    let log_normal = Summary::of(sizes(&config, &mut rng).iter().map(|size| size.ln()));
+    assert!((log_normal.mean - 30f64.ln()).abs() < 0.02);
+    assert!((log_normal.sd - 0.1).abs() < 0.01);
+    assert_eq!("log_normal".parse(), Ok(MutationKernel::LogNormal));
+
+    // a relative rate of 0 keeps size from mutating while the other genes still do
+    config.relative_mutation_rates[Gene::SIZE] = 0.0;
+    let child = parent.mix(&parent, &config, &mut rng);
+    assert_eq!(child[Gene::SIZE], 30.0);
+    assert_ne!(child[Gene::FLAGELLUM_SIZE], 5.0);
+    assert_eq!(child[Gene::MUTATION_RATE], 1.0);
+
+    // cells carry their own rate, so a rate of 0 is passed on without any mutations
+    config.evolvable_mutation_rate = true;
+    let mut stable = parent;
+    stable[Gene::MUTATION_RATE] = 0.0;
+    assert_eq!(stable.mix(&stable, &config, &mut rng), stable);
+
+    let mut config = SimulatorConfig::new(Reproduction::Sexual);
+    config.seed = 23;
+    config.evolvable_mutation_rate = true;
+    config.mutation_chance = 0.3;
+    config.mutation_kernel = MutationKernel::LogNormal;
+    let mut simulator = Simulator::with_config(config);
+    for _ in 0..1_000 {
+        simulator.simulate_step();
+    }
+    let rates = simulator.gene_values(Gene::MUTATION_RATE);
+    assert!(rates.iter().all(|rate| (0.0..=1.0).contains(rate)));
+    assert!(rates.iter().any(|&rate| rate != 0.3));
+}
+
+#[test]
+fn diploid_genes_follow_mendelian_inheritance() {
+    let mut config = SimulatorConfig::new(Reproduction::Sexual);
+    config.ploidy = Ploidy::Diploid;
+    config.mutation_chance = 0.0;
+    let mut rng = Random::new(24);
+
+    let homozygous = |size: f64, stomach_size: f64, rng: &mut Random| {
+        let mut genotype = Genotype::new(&config, rng);
+        for genes in [&mut genotype.maternal, &mut genotype.paternal] {
+            genes[Gene::SIZE] = size;
+            genes[Gene::STOMACH_SIZE] = stomach_size;
+        }
+        genotype
+    };
+    let small = homozygous(20.0, 5.0, &mut rng);
+    let large = homozygous(40.0, 15.0, &mut rng);
+
+    // every child of two homozygous parents gets one allele from each
+    let hybrid = small.offspring(Some(&large), &config, &mut rng);
+    assert_eq!(hybrid.maternal[Gene::SIZE], 20.0);
+    assert_eq!(hybrid.paternal[Gene::SIZE], 40.0);
+    assert!(hybrid.is_heterozygous(Gene::SIZE));
+    assert_eq!(hybrid.phenotype(&config)[Gene::SIZE], 30.0);
+    config.dominance[Gene::SIZE] = 1.0;
+    assert_eq!(hybrid.phenotype(&config)[Gene::SIZE], 40.0);
+    config.dominance[Gene::SIZE] = 0.0;
+    assert_eq!(hybrid.phenotype(&config)[Gene::SIZE], 20.0);
+
+    // crossing hybrids gives 3 large to 1 small when large is dominant
+    config.dominance[Gene::SIZE] = 1.0;
+    let children: Vec<Genotype> = (0..4_000)
+        .map(|_| hybrid.offspring(Some(&hybrid), &config, &mut rng))
+        .collect();
+    let small_share = children
+        .iter()
+        .filter(|child| child.phenotype(&config)[Gene::SIZE] == 20.0)
+        .count() as f64
+        / children.len() as f64;
+    assert!((small_share - 0.25).abs() < 0.03, "{}", small_share);
+
+    // stomach size follows size unless gametes cross over between them
+    let recombinant_share = |config: &SimulatorConfig, rng: &mut Random| {
+        let gametes: Vec<Genes> = (0..4_000).map(|_| hybrid.gamete(config, rng)).collect();
+        gametes
+            .iter()
+            .filter(|gamete| (gamete[Gene::SIZE] == 20.0) != (gamete[Gene::STOMACH_SIZE] == 5.0))
+            .count() as f64
+            / gametes.len() as f64
+    };
+    config.crossover_map[Gene::STOMACH_SIZE] = 0.0;
+    assert_eq!(recombinant_share(&config, &mut rng), 0.0);
+    config.crossover_map[Gene::STOMACH_SIZE] = 0.5;
+    assert!((recombinant_share(&config, &mut rng) - 0.5).abs() < 0.03);
+
+    // asexual children are clones
+    assert_eq!(hybrid.offspring(None, &config, &mut rng), hybrid);
+
+    // the genes of diploid cells are always their genotype's phenotype
+    config.seed = 24;
+    config.mutation_chance = 0.2;
+    config.dominance = Default::default();
+    let mut simulator = Simulator::with_config(config);
+    for _ in 0..1_500 {
+        simulator.simulate_step();
+    }
+    assert!(simulator
+        .get_cells()
+        .iter()
+        .any(|cell| cell.id().0 >= config.cell_number as u64));
+    for cell in simulator.get_cells() {
+        let genotype = cell.genotype.expect("diploid cells have a genotype");
+        assert_eq!(cell.genes, genotype.phenotype(&config));
+    }
+    assert!(seeded_simulator(Reproduction::Sexual, 24)
+        .get_cells()
+        .iter()
+        .all(|cell| cell.genotype.is_none()));
+    assert_eq!("diploid".parse(), Ok(Ploidy::Diploid));
+}
+
+#[test]
+fn cells_mate_in_the_same_box() {
+    let mut config = SimulatorConfig::new(Reproduction::Sexual);
+    config.seed = 26;
+    config.cell_number = 0;
+    let mut simulator = Simulator::with_config(config);
+    let mut rng = Random::new(26);
+    // the boxes of the default reproduction distance are 1..100 and 100..150 across
+    let ids: Vec<CellId> = [60, 95, 105, 140]
+        .iter()
+        .map(|&x| {
+            let genes = Genes::new(&config, &mut rng);
+            let mut cell = Cell::new(genes, x, 60, 5.0, &config, &mut rng);
+            cell.reproduction_cooldown = 0;
+            simulator.add_cell(cell)
+        })
+        .collect();
+    simulator.simulate_step();
+
+    // the second and third cells are the closest, but the first cell in each box waits there
+    // and carries the child of the next one to arrive
+    let fathers: Vec<Option<CellId>> = ids
+        .iter()
+        .map(|&id| simulator.get_cell(id).unwrap().child_father())
+        .collect();
+    assert_eq!(fathers, vec![Some(ids[1]), None, Some(ids[3]), None]);
+}
+
+#[test]
+fn mating_types_restrict_who_can_mate() {
+    // a world of founders with the given types
+    let founded = |mating_types: MatingTypes, founder_types: &[u32]| {
+        let mut config = SimulatorConfig::new(Reproduction::Sexual);
+        config.seed = 25;
+        config.cell_number = 0;
+        config.mating_types = mating_types;
+        let mut simulator = Simulator::with_config(config);
+        let mut rng = Random::new(25);
+        for i in 0..60 {
+            let genes = Genes::new(&config, &mut rng);
+            let x = Cell::get_random_pos_in_bounds(&config, &mut rng);
+            let y = Cell::get_random_pos_in_bounds(&config, &mut rng);
+            let mut cell = Cell::new(genes, x, y, 5.0, &config, &mut rng);
+            cell.mating_type = Some(founder_types[i % founder_types.len()]);
+            simulator.add_cell(cell);
+        }
+        for _ in 0..1_500 {
+            simulator.simulate_step();
+        }
+        simulator
+    };
+    let has_children = |simulator: &Simulator| {
+        simulator
+            .get_genealogy()
+            .records()
+            .any(|record| record.mother.is_some())
+    };
+
+    // cells of one type never mate
+    assert!(!has_children(&founded(MatingTypes::Sexes, &[FEMALE])));
+    assert!(!has_children(&founded(
+        MatingTypes::SelfIncompatible(3),
+        &[2]
+    )));
+
+    // children only get their parents' types
+    let simulator = founded(MatingTypes::SelfIncompatible(3), &[0, 1]);
+    assert!(has_children(&simulator));
+    assert!(simulator
+        .get_cells()
+        .iter()
+        .all(|cell| matches!(cell.mating_type, Some(0) | Some(1))));
+
+    // with sexes only females carry children, and the sex ratio is tracked
+    let mut config = SimulatorConfig::new(Reproduction::Sexual);
+    config.seed = 25;
+    config.mating_types = MatingTypes::Sexes;
+    let mut simulator = Simulator::with_config(config);
+    for _ in 0..1_500 {
+        simulator.simulate_step();
+        assert!(simulator
+            .get_cells()
+            .iter()
+            .filter(|cell| cell.child_father().is_some())
+            .all(|cell| cell.mating_type == Some(FEMALE)));
+    }
+    assert!(has_children(&simulator));
+    let shares: Vec<f64> = Metric::mating_types(config.mating_types)
+        .into_iter()
+        .map(|metric| metric.measure(&simulator))
+        .collect();
+    assert_eq!(shares.len(), 2);
+    assert!((shares[0] + shares[1] - 100.0).abs() < 1e-9);
+    assert!(shares.iter().all(|&share| share > 20.0));
+    assert_eq!(Metric::MatingTypeShare(MALE).id(), "mating_type_1");
+    assert_eq!("mating_type_1".parse(), Ok(Metric::MatingTypeShare(MALE)));
+
+    assert_eq!("3".parse(), Ok(MatingTypes::SelfIncompatible(3)));
+    assert!("1".parse::<MatingTypes>().is_err());
+    assert!(seeded_simulator(Reproduction::Sexual, 25)
+        .get_cells()
+        .iter()
+        .all(|cell| cell.mating_type.is_none()));
+}
+
+#[test]
+fn snapshots_are_plain_json() {
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 19);
+    for _ in 0..700 {
+        simulator.simulate_step();
+    }
+
+    let snapshot: serde_json::Value = serde_json::from_str(&simulator.snapshot_json()).unwrap();
+    assert_eq!(snapshot["step"], 700);
+    assert_eq!(snapshot["extinction_step"], serde_json::Value::Null);
+    assert_eq!(snapshot["config"]["food_density"], 240);
+
+    let cells = snapshot["cells"].as_array().unwrap();
+    assert_eq!(cells.len(), simulator.get_cells().len());
+    for (json, cell) in cells.iter().zip(simulator.get_cells()) {
+        assert_eq!(json["id"], cell.id().0);
+        assert_eq!(
+            json["genes"]["flagellum_size"],
+            cell.genes[Gene::FLAGELLUM_SIZE]
+        );
+        assert!(json["stomach_amount"].is_number());
+    }
+
+    let food_available: usize = simulator
+        .get_food()
+        .iter()
+        .map(|row| row.iter().flatten().count())
+        .sum();
+    assert_eq!(snapshot["food"].as_array().unwrap().len(), food_available);
+}
+
+#[test]
+fn time_series_stream_as_ndjson() {
+    let mut recorder = Recorder::new(100, String::new());
+    recorder
+        .set_format(RowFormat::Ndjson)
+        .add_builtin(Metric::PopulationSize)
+        .add_builtin(Metric::TimeToExtinction);
+    let mut simulator = seeded_simulator(Reproduction::Asexual, 2);
+    recorder.run(&mut simulator, 300).unwrap();
+    let rows: Vec<serde_json::Value> = recorder
+        .into_sink()
+        .lines()
+        .map(|row| serde_json::from_str(row).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[2]["step"], 300);
+    assert_eq!(
+        rows[2]["Population Size"],
+        simulator.get_cells().len() as f64
+    );
+    assert_eq!(rows[2]["Time to Extinction"], serde_json::Value::Null);
+
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 5);
+    experiment.replicates = 2;
+    experiment.steps = 400;
+    experiment.sample_interval = 200;
+    let results =
+        experiment::run_experiment(&experiment, |_| {}, &CancellationToken::new()).unwrap();
+    let mut recorder = Recorder::new(200, String::new());
+    recorder.set_format(RowFormat::Ndjson);
+    for sample in &results.samples {
+        sample
+            .write(&results.metrics, SampleColumns::Means, &mut recorder)
+            .unwrap();
+    }
+    let lines: Vec<serde_json::Value> = recorder
+        .into_sink()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    let size = &lines[1]["metrics"]["size"];
+    assert_eq!(lines[1]["step"], 400);
+    assert_eq!(size["mean"], results.samples[1].mean(2));
+    assert_eq!(size["count"], 2);
+    assert_eq!(size["replicates"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn timeline_changes_the_config_as_steps_advance() {
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 13);
+    let mut timeline = Timeline::new();
+    timeline
+        .push(
+            ConfigField::FoodDensity,
+            Change::Ramp {
+                start: 100,
+                end: 300,
+                from: 200.0,
+                to: 600.0,
+            },
+        )
+        .push(
+            ConfigField::MutationChance,
+            Change::Periodic {
+                start: 0,
+                period: 100,
+                low: 0.0,
+                high: 0.1,
+                waveform: Waveform::Square,
+            },
+        )
+        .push(
+            ConfigField::ReproductionCooldown,
+            Change::Set {
+                step: 250,
+                value: 50.0,
+            },
+        );
+    simulator.set_timeline(timeline);
+    assert_eq!(simulator.get_config().food_density, 240);
+    assert_eq!(simulator.get_config().mutation_chance, 0.0);
+
+    let mut seen = Vec::new();
+    for _ in 0..400 {
+        simulator.simulate_step();
+        let config = simulator.get_config();
+        seen.push((
+            simulator.get_steps(),
+            config.food_density,
+            config.mutation_chance,
+            config.reproduction_cooldown,
+        ));
+    }
+    assert_eq!(seen[99], (100, 200, 0.0, 200));
+    assert_eq!(seen[149], (150, 300, 0.1, 200));
+    assert_eq!(seen[249], (250, 500, 0.1, 50));
+    assert_eq!(seen[399], (400, 600, 0.0, 50));
+
+    // the timeline is part of the saved state
+    let mut loaded = Simulator::load(&simulator.save()).unwrap();
+    assert_eq!(loaded.get_timeline(), simulator.get_timeline());
+    for _ in 0..60 {
+        simulator.simulate_step();
+        loaded.simulate_step();
+    }
+    assert_eq!(loaded.get_config().mutation_chance, 0.1);
+    assert_eq!(loaded.save(), simulator.save());
+
+    // finished changes don't undo changes made by hand
+    simulator.get_config_mut().food_density = 100;
+    simulator.get_config_mut().reproduction_cooldown = 10;
+    simulator.simulate_step();
+    assert_eq!(simulator.get_config().food_density, 100);
+    assert_eq!(simulator.get_config().reproduction_cooldown, 10);
+}
+
+#[test]
+fn periodic_changes_cycle_between_low_and_high() {
+    let sine = Change::Periodic {
+        start: 10,
+        period: 40,
+        low: 100.0,
+        high: 300.0,
+        waveform: Waveform::Sine,
+    };
+    assert_eq!(sine.value_at(9), None);
+    assert_eq!(sine.value_at(10), Some(100.0));
+    assert!((sine.value_at(20).unwrap() - 200.0).abs() < 1e-9);
+    assert_eq!(sine.value_at(30), Some(300.0));
+    assert_eq!(sine.value_at(50), Some(100.0));
+
+    let phases = Timeline::phases(ConfigField::FoodDensity, &[240.0, 600.0, 100.0], 1_000);
+    let steps: Vec<Option<f64>> = phases
+        .changes()
+        .iter()
+        .map(|scheduled| match scheduled.change {
+            Change::Set { step, .. } => Some(step as f64),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(steps, vec![Some(0.0), Some(334.0), Some(667.0)]);
 }
 
+#[test]
+fn spatial_grid_tracks_moves_and_removals() {
+    let mut grid = SpatialGrid::new(1_000, 1_000, 50);
+    grid.insert(1, 10, 10);
+    grid.insert(2, 500, 500);
+    grid.update(1, 10, 10, 470, 510);
+
+    let mut near = grid.query_radius(490, 510, 30.0);
+    near.sort_unstable();
+    assert_eq!(near, vec![1, 2]);
+    assert_eq!(grid.nearest(0, 0, |_| true), Some(1));
+
+    assert!(grid.remove(1, 470, 510));
+    assert!(!grid.remove(1, 470, 510));
+    assert_eq!(grid.nearest(0, 0, |_| true), Some(2));
+    assert_eq!(grid.nearest(0, 0, |key| key != 2), None);
+    assert_eq!(grid.len(), 1);
+}
+
+#[test]
+fn seeded_experiment_is_reproducible() {
+    let mut experiment = seeded_experiment(Reproduction::Sexual, 7);
+    experiment.replicates = 2;
+    experiment.steps = 2_500;
+    experiment.timeline = Timeline::phases(ConfigField::FoodDensity, &[240.0, 600.0], 2_500);
+    experiment.sample_interval = 1_000;
+
+    let mut progress = Vec::new();
+    let cancel = CancellationToken::new();
+    let results =
+        experiment::get_results_csv(&experiment, |percent| progress.push(percent), &cancel)
+            .unwrap();
+    assert_eq!(
+        results,
+        experiment::get_results_csv(&experiment, |_| {}, &cancel).unwrap()
+    );
+    assert_eq!(progress, vec![40, 80, 100]);
+
+    let steps: Vec<&str> = results
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').next().unwrap())
+        .collect();
+    assert_eq!(steps, vec!["1000", "2000", "2500"]);
+}
+
+#[test]
+fn experiment_records_only_the_chosen_metrics() {
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 4);
+    experiment.replicates = 2;
+    experiment.steps = 900;
+    experiment.sample_interval = 300;
+    let cancel = CancellationToken::new();
+    let all = experiment::get_results_csv(&experiment, |_| {}, &cancel).unwrap();
+
+    experiment.metrics = vec![Metric::GeneMean(Gene::STOMACH_SIZE), Metric::PopulationSize];
+    let chosen = experiment::get_results_csv(&experiment, |_| {}, &cancel).unwrap();
+
+    let mut chosen_rows = chosen.lines();
+    assert_eq!(
+        chosen_rows.next(),
+        Some("Step #,Avg. Stomach Size,Population Size")
+    );
+    for (all_row, chosen_row) in all.lines().skip(1).zip(chosen_rows) {
+        let all_row: Vec<&str> = all_row.split(',').collect();
+        assert_eq!(chosen_row, [all_row[0], all_row[5], all_row[1]].join(","));
+    }
+    assert_eq!(
+        "gestation_steps".parse(),
+        Ok(Metric::GeneMean(Gene::GESTATION_STEPS))
+    );
+    assert!("speed".parse::<Metric>().is_err());
+}
+
+#[test]
+fn gene_distributions_are_sampled_and_snapshotted() {
+    let mut simulator = seeded_simulator(Reproduction::Sexual, 19);
+    for _ in 0..600 {
+        simulator.simulate_step();
+    }
+    let mut sizes = simulator.gene_values(Gene::SIZE);
+    sizes.sort_by(f64::total_cmp);
+    assert_eq!(
+        simulator.gene_quantiles(Gene::SIZE, &[0.0, 1.0]),
+        vec![sizes[0], sizes[sizes.len() - 1]]
+    );
+    assert_eq!(distribution::quantile(&[1.0, 2.0, 3.0, 4.0], 0.5), 2.5);
+    assert_eq!(distribution::quantile(&[1.0, 2.0, 3.0, 4.0], 0.25), 1.75);
+
+    let histogram = simulator.gene_histogram(Gene::SIZE, 20.0, 40.0, 4);
+    assert_eq!(histogram.edges, vec![20.0, 25.0, 30.0, 35.0, 40.0]);
+    let counted: usize = histogram.counts.iter().sum();
+    assert_eq!(counted + histogram.below + histogram.above, sizes.len());
+
+    // the experiment's bins hold the same cells as the snapshot's
+    let bins = Metric::histogram(Gene::SIZE, 20.0, 40.0, 4);
+    for (bin, &count) in bins.iter().zip(&histogram.counts) {
+        let share = bin.measure(&simulator);
+        assert!((share - count as f64 / sizes.len() as f64 * 100.0).abs() < 1e-9);
+    }
+    assert_eq!(bins[1].id(), "size_bin_25_30");
+    assert_eq!(bins[1].id().parse(), Ok(bins[1]));
+    assert_eq!(
+        "flagellum_size_p90".parse(),
+        Ok(Metric::GeneQuantile {
+            gene: Gene::FLAGELLUM_SIZE,
+            quantile: 0.9
+        })
+    );
+    assert!("size_p150".parse::<Metric>().is_err());
+    assert!("size_bin_30_20".parse::<Metric>().is_err());
+
+    let mut experiment = seeded_experiment(Reproduction::Sexual, 19);
+    experiment.replicates = 2;
+    experiment.steps = 600;
+    experiment.sample_interval = 600;
+    experiment.metrics = Metric::quantiles(Gene::SIZE, &[0.1, 0.9]);
+    experiment.metrics.extend(bins);
+    let csv = experiment::get_results_csv(&experiment, |_| {}, &CancellationToken::new()).unwrap();
+    let mut rows = csv.lines();
+    assert_eq!(
+        rows.next(),
+        Some("Step #,Size P10,Size P90,% Size 20 to 25,% Size 25 to 30,% Size 30 to 35,% Size 35 to 40")
+    );
+    let row: Vec<f64> = rows
+        .next()
+        .unwrap()
+        .split(',')
+        .map(|value| value.parse().unwrap())
+        .collect();
+    assert!(row[1] <= row[2]);
+    assert!(row[3..].iter().sum::<f64>() <= 100.0 + 1e-9);
+}
+
+#[test]
+fn morris_screening_ranks_model_parameters() {
+    let mut model = ModelParameters::default();
+    ModelParameter::ReproductionDistance.set(&mut model, 41.6);
+    assert_eq!(model.reproduction_distance, 42);
+    assert_eq!("base_speed".parse(), Ok(ModelParameter::BaseSpeed));
+
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 20);
+    experiment.replicates = 2;
+    experiment.steps = 600;
+    experiment.sample_interval = 300;
+    experiment.metrics = vec![Metric::PopulationSize, Metric::GeneMean(Gene::SIZE)];
+    let mut morris = Morris::new(experiment);
+    morris.trajectories = 3;
+    morris
+        .vary(ModelParameter::BirthChanceMultiplier, 0.05, 0.3)
+        // asexual cells never look for mates, so this can't matter
+        .vary_around(ModelParameter::ReproductionDistance, 0.5)
+        .vary_around(ModelParameter::BaseSpeed, 0.2);
+    assert_eq!(
+        morris.parameters()[2],
+        (ModelParameter::BaseSpeed, 4.0, 6.0)
+    );
+
+    // every trajectory moves each parameter once, by 2/3 of its range with 4 levels
+    let design = morris.design();
+    assert_eq!(design.len(), 3 * 4);
+    for trajectory in design.chunks(4) {
+        let mut moved = Vec::new();
+        for pair in trajectory.windows(2) {
+            let changed: Vec<usize> = (0..3).filter(|&i| pair[0][i] != pair[1][i]).collect();
+            assert_eq!(changed.len(), 1);
+            assert!(((pair[1][changed[0]] - pair[0][changed[0]]).abs() - 2.0 / 3.0).abs() < 1e-12);
+            moved.push(changed[0]);
+        }
+        moved.sort();
+        assert_eq!(moved, vec![0, 1, 2]);
+    }
+    assert!(design.iter().flatten().all(|&x| (0.0..=1.0).contains(&x)));
+
+    let results = morris.run(|_| {}, &CancellationToken::new()).unwrap();
+    assert_eq!(results.effects.len(), 2 * 2 * 3);
+    let ranking = results.ranking(Metric::PopulationSize);
+    assert_eq!(ranking.len(), 3);
+    assert!(ranking
+        .windows(2)
+        .all(|pair| pair[0].mu_star >= pair[1].mu_star));
+    let distance = ranking
+        .iter()
+        .find(|effect| effect.parameter == ModelParameter::ReproductionDistance)
+        .unwrap();
+    assert_eq!((distance.mu_star, distance.count), (0.0, 3));
+    assert_eq!(ranking[0].step, 600);
+
+    let csv = results.to_csv();
+    assert_eq!(
+        csv.lines().next(),
+        Some("parameter,low,high,step,metric,mu,mu_star,sigma,n")
+    );
+    assert_eq!(csv.lines().count(), 1 + 12);
+}
+
+#[test]
+fn summaries_skip_undefined_values() {
+    let summary = Summary::of(vec![2.0, f64::NAN, 4.0, 9.0, 5.0]);
+    assert_eq!(summary.count, 4);
+    assert_eq!(summary.mean, 5.0);
+    assert!((summary.sd - (26.0f64 / 3.0).sqrt()).abs() < 1e-12);
+    assert!((summary.se - summary.sd / 2.0).abs() < 1e-12);
+    assert!((summary.ci95_high - (5.0 + 3.182 * summary.se)).abs() < 1e-12);
+    assert!((summary.ci95_low - (5.0 - 3.182 * summary.se)).abs() < 1e-12);
+    assert_eq!(summary.median, 4.5);
+
+    let single = Summary::of(vec![f64::NAN, 7.0]);
+    assert_eq!((single.count, single.mean, single.median), (1, 7.0, 7.0));
+    assert!(single.sd.is_nan() && single.ci95_low.is_nan());
+
+    let extinct = Summary::of(vec![f64::NAN, f64::NAN]);
+    assert_eq!(extinct.count, 0);
+    assert!(extinct.mean.is_nan() && extinct.median.is_nan());
+}
+
+#[test]
+fn experiment_outputs_every_replicate_and_their_spread() {
+    let mut experiment = seeded_experiment(Reproduction::Sexual, 6);
+    experiment.replicates = 3;
+    experiment.steps = 1_000;
+    experiment.sample_interval = 500;
+    experiment.metrics = vec![
+        Metric::PopulationSize,
+        Metric::GeneMean(Gene::FLAGELLUM_SIZE),
+    ];
+    // written while the experiment runs, like the command line does
+    let mut replicates = Recorder::new(500, String::new());
+    let mut summary = Recorder::new(500, String::new());
+    let mut steps = Vec::new();
+    let results = experiment::stream_experiments(
+        std::slice::from_ref(&experiment),
+        |index, sample| {
+            assert_eq!(index, 0);
+            steps.push(sample.step);
+            let metrics = &experiment.metrics;
+            sample
+                .write(metrics, SampleColumns::Replicates, &mut replicates)
+                .unwrap();
+            sample
+                .write(metrics, SampleColumns::Summary, &mut summary)
+                .unwrap();
+        },
+        |_| {},
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    assert_eq!(steps, vec![500, 1_000]);
+    assert_eq!(results[0].samples.len(), 2);
+
+    let replicates = replicates.into_sink();
+    let rows: Vec<Vec<f64>> = replicates
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').map(|value| value.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(rows.len(), 2 * 3);
+    assert_eq!(&rows[3][..2], &[1_000.0, 0.0]);
+
+    let summary = summary.into_sink();
+    let header: Vec<&str> = summary.lines().next().unwrap().split(',').collect();
+    assert_eq!(header.len(), 1 + 2 * 7);
+    assert_eq!(header[3], "Population Size SE");
+    let last: Vec<f64> = summary
+        .lines()
+        .last()
+        .unwrap()
+        .split(',')
+        .map(|value| value.parse().unwrap())
+        .collect();
+    let populations = Summary::of(rows[3..].iter().map(|row| row[2]));
+    assert_eq!(last[1], populations.mean);
+    assert_eq!(last[6], populations.median);
+    assert_eq!(last[7], 3.0);
+}
+
+#[test]
+fn experiments_run_together_match_separate_runs() {
+    let mut asexual = seeded_experiment(Reproduction::Asexual, 11);
+    asexual.replicates = 3;
+    asexual.steps = 1_500;
+    asexual.sample_interval = 500;
+
+    let mut sexual = seeded_experiment(Reproduction::Sexual, 12);
+    sexual.config.food_density = 600;
+    sexual.replicates = 2;
+    sexual.steps = 800;
+    sexual.sample_interval = 300;
+
+    let mut progress = Vec::new();
+    let cancel = CancellationToken::new();
+    let results = experiment::get_results_csvs(
+        &[asexual.clone(), sexual.clone()],
+        |percent| progress.push(percent),
+        &cancel,
+    )
+    .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            experiment::get_results_csv(&asexual, |_| {}, &cancel).unwrap(),
+            experiment::get_results_csv(&sexual, |_| {}, &cancel).unwrap(),
+        ]
+    );
+    assert_eq!(results[1].lines().count(), 1 + 3);
+    assert_eq!(progress.last(), Some(&100));
+    assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn sweep_runs_every_combination_into_a_long_table() {
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 8);
+    experiment.replicates = 2;
+    experiment.steps = 600;
+    experiment.sample_interval = 300;
+    experiment.metrics = vec![Metric::PopulationSize, Metric::GeneMean(Gene::SIZE)];
+
+    let mut sweep = Sweep::new(experiment.clone());
+    sweep
+        .vary(ConfigField::MutationChance, vec![0.01, 0.2])
+        .vary_range(ConfigField::FoodDensity, 100.0, 500.0, 200.0);
+    assert_eq!(
+        sweep.combinations(),
+        vec![
+            vec![0.01, 100.0],
+            vec![0.01, 300.0],
+            vec![0.01, 500.0],
+            vec![0.2, 100.0],
+            vec![0.2, 300.0],
+            vec![0.2, 500.0],
+        ]
+    );
+
+    let cancel = CancellationToken::new();
+    let mut recorder = Recorder::new(300, String::new());
+    sweep
+        .stream(
+            |combination, sample| {
+                sweep
+                    .write_sample(combination, sample, &mut recorder)
+                    .unwrap()
+            },
+            |_| {},
+            &cancel,
+        )
+        .unwrap();
+    let table = recorder.into_sink();
+    let rows: Vec<&str> = table.lines().collect();
+    assert_eq!(
+        rows[0],
+        "mutation_chance,food_density,step,metric,mean,sd,se,ci95_low,ci95_high,median,n"
+    );
+    // 6 combinations, 2 samples and 2 metrics each
+    assert_eq!(rows.len(), 1 + 6 * 2 * 2);
+
+    // every combination matches running its experiment on its own
+    experiment.config.mutation_chance = 0.2;
+    experiment.config.food_density = 300;
+    let single = experiment::run_experiment(&experiment, |_| {}, &cancel).unwrap();
+    let expected = format!("0.2,300,600,size,{},", single.samples[1].mean(1));
+    assert!(rows.iter().any(|row| row.starts_with(&expected)));
+
+    let mut range = Sweep::new(experiment);
+    range.vary_range(ConfigField::MutationPercentChange, 0.1, 0.3, 0.1);
+    assert_eq!(range.parameters()[0].1, vec![0.1, 0.2, 0.3]);
+    range.vary_range(ConfigField::MutationPercentChange, 0.1, 0.1, 0.1);
+    assert_eq!(range.parameters()[0].1, vec![0.1]);
+}
+
+#[test]
+fn sweep_rows_show_the_applied_values() {
+    assert!(ConfigField::FoodDensity.check(0.0).is_err());
+    assert!(ConfigField::ReproductionCooldown.check(-5.0).is_err());
+    assert!(ConfigField::MutationChance.check(1.5).is_err());
+    assert!(ConfigField::MutationChance.check(1.0).is_ok());
+
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 8);
+    experiment.steps = 100;
+    experiment.sample_interval = 100;
+    let mut sweep = Sweep::new(experiment);
+    sweep.vary(ConfigField::FoodDensity, vec![100.4]);
+    let results = sweep.run(|_| {}, &CancellationToken::new()).unwrap();
+    assert_eq!(results.combinations, vec![vec![100.0]]);
+}
+
+#[test]
+fn cancelled_experiment_stops_early() {
+    let mut experiment = seeded_experiment(Reproduction::Asexual, 3);
+    experiment.replicates = 2;
+    experiment.steps = 1_000_000;
+    experiment.sample_interval = 100;
+
+    // cancelled from the observer after the first sample
+    let cancel = CancellationToken::new();
+    let mut progress = Vec::new();
+    let result = experiment::get_results_csv(
+        &experiment,
+        |percent| {
+            progress.push(percent);
+            cancel.cancel();
+        },
+        &cancel,
+    );
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(progress, vec![0]);
+
+    // cancelled from another thread in the middle of a sample
+    experiment.sample_interval = 1_000_000;
+    let cancel = CancellationToken::new();
+    let canceller = {
+        let cancel = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            cancel.cancel();
+        })
+    };
+    let result = experiment::get_results_csv(&experiment, |_| {}, &cancel);
+    canceller.join().unwrap();
+    assert_eq!(result, Err(Cancelled));
+}
+
+// The golden runs have to behave the same when compiled to WebAssembly
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen_test]
+fn golden_runs_match_in_the_browser() {
+    for run in golden_runs() {
+        run.check();
+    }
+}
 
 // This runs a unit test in the browser, and in addition it supports asynchronous Future APIs.
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test(async)]
 fn async_test() -> impl Future<Item = (), Error = JsValue> {
     // Creates a JavaScript Promise which will asynchronously resolve with the value 42.
@@ -28,8 +1622,7 @@ fn async_test() -> impl Future<Item = (), Error = JsValue> {
 
     // Converts that Promise into a Future.
     // The unit test will wait for the Future to resolve.
-    JsFuture::from(promise)
-        .map(|x| {
-            assert_eq!(x, 42);
-        })
+    JsFuture::from(promise).map(|x| {
+        assert_eq!(x, 42);
+    })
 }
@@ -31,6 +31,8 @@ module.exports = {
 
     new WasmPackPlugin({
       crateDirectory: __dirname,
+      // the browser bindings are behind the `wasm` cargo feature
+      extraArgs: "-- --features wasm",
     }),
   ]
 };